@@ -0,0 +1,81 @@
+use std::collections::VecDeque;
+
+use shared::MAX_CHAT_MESSAGE_LENGTH;
+
+/// How many received chat messages are kept for the chat log overlay.
+const CHAT_LOG_SIZE: usize = 6;
+
+pub struct ChatState {
+    typing: bool,
+    input: String,
+    outgoing: Vec<String>,
+    log: VecDeque<(String, String)>,
+}
+
+impl ChatState {
+    pub fn new() -> ChatState {
+        ChatState {
+            typing: false,
+            input: String::new(),
+            outgoing: Vec::new(),
+            log: VecDeque::new(),
+        }
+    }
+
+    pub fn is_typing(&self) -> bool {
+        self.typing
+    }
+
+    pub fn open(&mut self) {
+        self.typing = true;
+        self.input.clear();
+    }
+
+    pub fn cancel(&mut self) {
+        self.typing = false;
+        self.input.clear();
+    }
+
+    /// Closes the text entry and queues the typed message to be sent.
+    pub fn submit(&mut self) {
+        let text = self.input.trim().to_string();
+
+        if !text.is_empty() {
+            self.outgoing.push(text);
+        }
+
+        self.cancel();
+    }
+
+    pub fn type_text(&mut self, text: &str) {
+        for character in text.chars() {
+            if self.input.chars().count() < MAX_CHAT_MESSAGE_LENGTH {
+                self.input.push(character);
+            }
+        }
+    }
+
+    pub fn backspace(&mut self) {
+        self.input.pop();
+    }
+
+    pub fn input(&self) -> &str {
+        &self.input
+    }
+
+    pub fn drain_outgoing(&mut self) -> Vec<String> {
+        self.outgoing.drain(..).collect()
+    }
+
+    pub fn receive(&mut self, from: String, text: String) {
+        self.log.push_back((from, text));
+
+        if self.log.len() > CHAT_LOG_SIZE {
+            self.log.pop_front();
+        }
+    }
+
+    pub fn log(&self) -> impl Iterator<Item = &(String, String)> {
+        self.log.iter()
+    }
+}
@@ -0,0 +1,69 @@
+use std::collections::VecDeque;
+
+use shared::{checksum::WorldChecksum, snapshot::EntitySnapshot};
+
+/// How many frames of our own player's predicted state are kept to compare with server checksums.
+const PREDICTION_HISTORY: usize = 120;
+
+/// Compares the world checksums sent by the server with the local world at the checksum's frame.
+///
+/// Replicated entities arrive in order with the checksum, so they are compared as they are when it
+/// arrives. Our own player is predicted ahead of the server, its state is kept per frame instead.
+pub struct DesyncDetector {
+    received: Option<WorldChecksum>,
+    /// The predicted state of our player after each frame, oldest first.
+    predictions: VecDeque<(u32, EntitySnapshot)>,
+}
+
+impl DesyncDetector {
+    pub fn new() -> DesyncDetector {
+        DesyncDetector {
+            received: None,
+            predictions: VecDeque::new(),
+        }
+    }
+
+    /// Keeps a checksum to compare, `snapshot_frame` is the frame of the world snapshot our world
+    /// started from. Checksums of frames before it describe a world we never had and are ignored.
+    pub fn receive(&mut self, checksum: WorldChecksum, snapshot_frame: Option<u32>) {
+        match snapshot_frame {
+            Some(snapshot_frame) if checksum.command_frame >= snapshot_frame => {
+                self.received = Some(checksum);
+            }
+            _ => {}
+        }
+    }
+
+    /// Takes the checksum received this tick, if any.
+    pub fn take_received(&mut self) -> Option<WorldChecksum> {
+        self.received.take()
+    }
+
+    /// Stores the state of our player after the given frame was simulated.
+    pub fn record_prediction(&mut self, command_frame: u32, player: EntitySnapshot) {
+        if let Some((last_frame, _)) = self.predictions.back() {
+            if *last_frame >= command_frame {
+                self.predictions.retain(|(frame, _)| *frame < command_frame);
+            }
+        }
+
+        self.predictions.push_back((command_frame, player));
+
+        while self.predictions.len() > PREDICTION_HISTORY {
+            self.predictions.pop_front();
+        }
+    }
+
+    /// The state of our player after the given frame, `None` if it is no longer or not yet known.
+    pub fn prediction_at(&self, command_frame: u32) -> Option<&EntitySnapshot> {
+        self.predictions
+            .iter()
+            .find(|(frame, _)| *frame == command_frame)
+            .map(|(_, player)| player)
+    }
+
+    pub fn reset(&mut self) {
+        self.received = None;
+        self.predictions.clear();
+    }
+}
@@ -0,0 +1,13 @@
+use sdl2::keyboard::Keycode;
+use shared::command::{ClientCommand, Move};
+
+/// The command a key triggers, if any.
+pub fn command_for_key(key: Keycode) -> Option<ClientCommand> {
+    match key {
+        Keycode::W => Some(ClientCommand::Move(Move::Up)),
+        Keycode::D => Some(ClientCommand::Move(Move::Right)),
+        Keycode::S => Some(ClientCommand::Move(Move::Down)),
+        Keycode::A => Some(ClientCommand::Move(Move::Left)),
+        _ => None,
+    }
+}
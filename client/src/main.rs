@@ -1,7 +1,12 @@
 use legion::systems::schedule::Builder;
 use shared::{
+    command::CommandQueue,
+    logging::initialize_logging,
+    map::TileMap,
     message::{ClientCommand, ClientMessage, ServerMessage},
-    systems::WindowResource,
+    replay::Replay,
+    statistics::NetworkStatistics,
+    systems::{client_handshake_system, simulation_systems, SimulationRole, WindowResource},
     ConnectionInformation,
 };
 
@@ -14,18 +19,51 @@ use net_sync::{
     compression::lz4::Lz4,
 };
 
-use crate::systems::{
-    client_render_system, handle_messages_system, handle_resimulation, move_player_system,
-    PressedInputBuffer,
+use crate::{
+    chat::ChatState,
+    desync::DesyncDetector,
+    replay_viewer::ReplayViewer,
+    simulation_speed::SimulationSpeedController,
+    systems::{
+        chat_system, client_render_system, desync_system, handle_messages_system,
+        move_player_system, network_statistics_system, resimulation_report_system,
+        session_system, ClientSettings, NetworkOverlay, PingTimer, PressedInputBuffer,
+    },
 };
 
+mod chat;
+mod desync;
+mod input;
+mod replay_viewer;
+mod simulation_speed;
 mod systems;
 
+const DEFAULT_TARGET_COMMAND_BUFFER: u32 = 2;
+
 fn main() {
     initialize_terminal();
 
     let window = initialize_window();
 
+    let mut args = std::env::args().skip(1).peekable();
+
+    if args.peek().map(String::as_str) == Some("--replay") {
+        let path = args.nth(1).expect("Usage: client --replay <file>");
+        let replay = Replay::load(&path)
+            .unwrap_or_else(|e| panic!("Failed to load replay {}: {}", path, e));
+
+        ReplayViewer::new(replay).run(&window);
+        return;
+    }
+
+    let name = args
+        .next()
+        .unwrap_or_else(|| format!("player-{}", std::process::id()));
+    let target_command_buffer = args
+        .next()
+        .and_then(|buffer| buffer.parse().ok())
+        .unwrap_or(DEFAULT_TARGET_COMMAND_BUFFER);
+
     let mut client = ClientWorldBuilder::<ServerMessage, ClientMessage, ClientCommand>::default()
         .with_tcp::<Bincode, Lz4>("127.0.0.1:1119".parse().unwrap())
         .register_systems(initialize_main_systems)
@@ -33,6 +71,15 @@ fn main() {
         .with_resource(window)
         .with_resource(ConnectionInformation::new())
         .with_resource(PressedInputBuffer::new())
+        .with_resource(CommandQueue::new())
+        .with_resource(NetworkStatistics::new())
+        .with_resource(NetworkOverlay::new())
+        .with_resource(PingTimer::new())
+        .with_resource(ClientSettings::new(name))
+        .with_resource(SimulationSpeedController::new(target_command_buffer))
+        .with_resource(TileMap::new())
+        .with_resource(ChatState::new())
+        .with_resource(DesyncDetector::new())
         .build();
 
     loop {
@@ -44,7 +91,7 @@ fn main() {
 }
 
 fn initialize_terminal() {
-    simple_logger::init().unwrap();
+    initialize_logging().unwrap_or_else(|e| panic!("Failed to initialize logging: {}", e));
 }
 
 fn initialize_window() -> WindowResource {
@@ -70,10 +117,19 @@ fn initialize_window() -> WindowResource {
 }
 
 fn initialize_main_systems(builder: Builder) -> Builder {
-    builder
+    let builder = builder
+        .add_system(client_handshake_system())
         .add_system(client_render_system())
         .add_system(move_player_system())
-        .add_system(handle_resimulation())
+        .add_system(resimulation_report_system());
+
+    simulation_systems(builder, SimulationRole::Client, |builder, _| builder)
         .add_system(handle_messages_system())
+        // Entities spawned from the messages have to exist before the world is checked.
+        .flush()
+        .add_system(desync_system())
+        .add_system(network_statistics_system())
+        .add_system(chat_system())
+        .add_system(session_system())
         .flush()
 }
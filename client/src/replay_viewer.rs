@@ -0,0 +1,351 @@
+use std::{
+    collections::HashSet,
+    time::{Duration, Instant},
+};
+
+use legion::{prelude::*, systems::SubWorld};
+use net_sync::clock::{FrameLimiter, FrameRateLimitStrategy};
+use sdl2::{event::Event, keyboard::Keycode, pixels::Color, rect::Rect, render::WindowCanvas};
+use shared::{
+    command::{ClientCommand, Command, CommandQueue, CommandTracker},
+    components::{Health, Pickup, PlayerType},
+    draw_entity, draw_health_bar, draw_map, draw_pickup,
+    map::TileMap,
+    replay::{Replay, ReplayEvent},
+    snapshot::EntitySnapshot,
+    systems::{apply_commands_system, WindowResource},
+    TextColumn, SCREEN_HEIGHT, SCREEN_WIDTH,
+};
+
+use crate::systems::{replay_viewer_entities_system, replay_viewer_events_system};
+
+const MIN_SPEED: f32 = 0.125;
+const MAX_SPEED: f32 = 8.0;
+const TIMELINE_Y: i32 = SCREEN_HEIGHT as i32 + 20;
+const TIMELINE_HEIGHT: u32 = 20;
+const VIEWER_FPS: u32 = 60;
+
+/// Plays back a server replay with the regular render code.
+///
+/// Entities are restored from the closest recorded snapshot and player commands recorded after it
+/// are applied on top, enemies and pickups only move at snapshot frames.
+pub struct ReplayViewer {
+    replay: Replay,
+    frame: u32,
+    last_frame: u32,
+    paused: bool,
+    speed: f32,
+    jump_input: String,
+    /// Frames from which a client resimulated after the server corrected its prediction.
+    resimulation_frames: HashSet<u32>,
+    /// The entities of the frame that was reconstructed last.
+    cached_entities: Option<(u32, Vec<EntitySnapshot>)>,
+}
+
+impl ReplayViewer {
+    pub fn new(replay: Replay) -> ReplayViewer {
+        let resimulation_frames = replay
+            .events
+            .iter()
+            .filter_map(|event| match event {
+                ReplayEvent::Resimulated { command_frame, .. } => Some(*command_frame),
+                _ => None,
+            })
+            .collect();
+
+        let frame = replay.events.first().map_or(0, |event| event.frame());
+        let last_frame = replay.last_frame();
+
+        ReplayViewer {
+            replay,
+            frame,
+            last_frame,
+            paused: false,
+            speed: 1.0,
+            jump_input: String::new(),
+            resimulation_frames,
+            cached_entities: None,
+        }
+    }
+
+    /// The entities at the current frame, only reconstructed when the frame changed.
+    fn current_entities(&mut self) -> &[EntitySnapshot] {
+        let frame = self.frame;
+
+        let is_cached = match &self.cached_entities {
+            Some((cached_frame, _)) => *cached_frame == frame,
+            None => false,
+        };
+
+        if !is_cached {
+            self.cached_entities = Some((frame, self.entities_at(frame)));
+        }
+
+        &self.cached_entities.as_ref().unwrap().1
+    }
+
+    /// Reconstructs all entities at the given frame.
+    ///
+    /// The closest snapshot before the frame is loaded into a world and the recorded commands
+    /// after it are applied frame by frame with the shared `apply_commands_system`.
+    pub fn entities_at(&self, frame: u32) -> Vec<EntitySnapshot> {
+        let events = &self.replay.events;
+        let end = events.partition_point(|event| event.frame() <= frame);
+
+        let snapshot = events[..end].iter().rev().find_map(|event| match event {
+            ReplayEvent::Snapshot(snapshot) => Some(snapshot),
+            _ => None,
+        });
+
+        let universe = Universe::new();
+        let mut world = universe.create_world();
+        let mut resources = Resources::default();
+        resources.insert(CommandQueue::new());
+        resources.insert(UntrackedCommands);
+        resources.insert(self.replay.header.map.clone());
+        resources.insert(ViewerFrame::new());
+
+        let mut schedule = Schedule::builder()
+            .add_system(replay_viewer_events_system())
+            .flush()
+            .add_system(apply_commands_system::<UntrackedCommands>())
+            .add_system(replay_viewer_entities_system())
+            .build();
+
+        let first_frame = match snapshot {
+            Some(snapshot) => {
+                resources
+                    .get_mut::<ViewerFrame>()
+                    .unwrap()
+                    .load(snapshot.entities.clone());
+                schedule.execute(&mut world, &mut resources);
+                snapshot.command_frame
+            }
+            None => events.first().map_or(frame, |event| event.frame()),
+        };
+
+        for step_frame in first_frame..=frame {
+            let frame_events = self
+                .replay
+                .events_at(step_frame)
+                .iter()
+                .filter(|event| !matches!(event, ReplayEvent::Snapshot(_)))
+                .cloned()
+                .collect();
+
+            // Commands of the snapshot frame are already part of the snapshot.
+            let apply_commands =
+                snapshot.map_or(true, |snapshot| snapshot.command_frame != step_frame);
+
+            resources
+                .get_mut::<ViewerFrame>()
+                .unwrap()
+                .step(step_frame, frame_events, apply_commands);
+            schedule.execute(&mut world, &mut resources);
+        }
+
+        let mut viewer_frame = resources.get_mut::<ViewerFrame>().unwrap();
+        viewer_frame.take_entities()
+    }
+
+    pub fn run(mut self, window_resource: &WindowResource) {
+        let mut assets = find_folder::Search::ParentsThenKids(3, 3)
+            .for_folder("assets")
+            .unwrap();
+        assets.push("FiraSans-Regular.ttf");
+
+        let mut canvas = window_resource.window_lock().unwrap();
+        let mut event_pump = window_resource.event_pump().unwrap();
+        let tff_context = window_resource.tff().unwrap();
+        let texture_creator = canvas.texture_creator();
+        let font = tff_context.load_font(&assets, 50).unwrap();
+
+        let frame_duration =
+            Duration::from_secs_f32(1.0 / self.replay.header.tick_rate.max(1) as f32);
+        let mut last_advance = Instant::now();
+        let mut limiter = FrameLimiter::new(FrameRateLimitStrategy::Sleep, VIEWER_FPS);
+
+        'running: loop {
+            for event in event_pump.poll_iter() {
+                match event {
+                    Event::Quit { .. } => break 'running,
+                    Event::KeyDown {
+                        keycode: Some(Keycode::Escape),
+                        ..
+                    } => break 'running,
+                    Event::KeyDown {
+                        keycode: Some(keycode),
+                        ..
+                    } => self.handle_key(keycode),
+                    _ => {}
+                }
+            }
+
+            let elapsed = last_advance.elapsed().as_secs_f32() * self.speed;
+
+            if !self.paused && elapsed >= frame_duration.as_secs_f32() {
+                if self.frame < self.last_frame {
+                    self.frame += 1;
+                } else {
+                    self.paused = true;
+                }
+                last_advance = Instant::now();
+            }
+
+            canvas.set_draw_color(Color::RGB(0, 0, 0));
+            canvas.clear();
+
+            draw_map(&mut canvas, &self.replay.header.map);
+
+            for entity in self.current_entities() {
+                let (x, y) = match entity.position {
+                    Some(position) => position,
+                    None => continue,
+                };
+
+                if let Some((kind, value)) = entity.pickup {
+                    draw_pickup(&mut canvas, x, y, &Pickup { kind, value });
+                }
+                if let Some(player_type) = entity.player_type {
+                    draw_entity(&mut canvas, x, y, &PlayerType { player_type });
+                }
+                if let Some((current, max)) = entity.health {
+                    draw_health_bar(&mut canvas, x, y, &Health { current, max });
+                }
+            }
+
+            self.draw_timeline(&mut canvas);
+
+            {
+                let mut hud = TextColumn::new(
+                    &mut canvas,
+                    &texture_creator,
+                    &font,
+                    SCREEN_WIDTH as i32 - 200,
+                    100,
+                );
+                hud.line(&format!("F: {}/{}", self.frame, self.last_frame));
+                hud.line(&format!("Speed: {}x", self.speed));
+
+                if self.paused {
+                    hud.line("Paused");
+                }
+                if !self.jump_input.is_empty() {
+                    hud.line(&format!("Jump: {}", self.jump_input));
+                }
+                if self.resimulation_frames.contains(&self.frame) {
+                    hud.line("Resim");
+                }
+            }
+
+            canvas.present();
+            limiter.wait();
+        }
+    }
+
+    /// Space pauses, left and right step while paused, up and down change the speed,
+    /// typing a frame number and pressing return jumps to it.
+    fn handle_key(&mut self, keycode: Keycode) {
+        match keycode {
+            Keycode::Space => self.paused = !self.paused,
+            Keycode::Right if self.paused => self.frame = (self.frame + 1).min(self.last_frame),
+            Keycode::Left if self.paused => self.frame = self.frame.saturating_sub(1),
+            Keycode::Up => self.speed = (self.speed * 2.0).min(MAX_SPEED),
+            Keycode::Down => self.speed = (self.speed / 2.0).max(MIN_SPEED),
+            Keycode::Backspace => {
+                self.jump_input.pop();
+            }
+            Keycode::Return => {
+                if let Ok(frame) = self.jump_input.parse::<u32>() {
+                    self.frame = frame.min(self.last_frame);
+                    self.paused = true;
+                }
+                self.jump_input.clear();
+            }
+            keycode => {
+                let digit = keycode.name();
+                if digit.len() == 1 && digit.chars().all(|c| c.is_ascii_digit()) {
+                    self.jump_input.push_str(&digit);
+                }
+            }
+        }
+    }
+
+    /// Draws the replay timeline with resimulation frames in red and the current frame in white.
+    fn draw_timeline(&self, canvas: &mut WindowCanvas) {
+        let width = SCREEN_WIDTH - 20;
+        let first_frame = self.replay.events.first().map_or(0, |event| event.frame());
+        let length = (self.last_frame - first_frame).max(1);
+        let x_of = |frame: u32| {
+            10 + ((frame - first_frame) as u64 * width as u64 / length as u64) as i32
+        };
+
+        canvas.set_draw_color(Color::RGB(60, 60, 60));
+        let _ = canvas.fill_rect(Rect::new(10, TIMELINE_Y, width, TIMELINE_HEIGHT));
+
+        canvas.set_draw_color(Color::RGB(255, 0, 0));
+        for frame in self.resimulation_frames.iter() {
+            let _ = canvas.fill_rect(Rect::new(x_of(*frame), TIMELINE_Y, 1, TIMELINE_HEIGHT));
+        }
+
+        canvas.set_draw_color(Color::RGB(255, 255, 255));
+        let _ = canvas.fill_rect(Rect::new(
+            x_of(self.frame.max(first_frame)),
+            TIMELINE_Y - 4,
+            3,
+            TIMELINE_HEIGHT + 8,
+        ));
+    }
+}
+
+/// The replay events of the frame the viewer steps through, and the entities after the step.
+pub struct ViewerFrame {
+    pub frame: u32,
+    /// Entities of a snapshot to spawn, only set for the first step.
+    pub snapshot: Vec<EntitySnapshot>,
+    pub events: Vec<ReplayEvent>,
+    pub apply_commands: bool,
+    pub entities: Vec<EntitySnapshot>,
+}
+
+impl ViewerFrame {
+    pub fn new() -> ViewerFrame {
+        ViewerFrame {
+            frame: 0,
+            snapshot: Vec::new(),
+            events: Vec::new(),
+            apply_commands: false,
+            entities: Vec::new(),
+        }
+    }
+
+    fn load(&mut self, snapshot: Vec<EntitySnapshot>) {
+        self.snapshot = snapshot;
+    }
+
+    fn step(&mut self, frame: u32, events: Vec<ReplayEvent>, apply_commands: bool) {
+        self.frame = frame;
+        self.events = events;
+        self.apply_commands = apply_commands;
+    }
+
+    fn take_entities(&mut self) -> Vec<EntitySnapshot> {
+        std::mem::take(&mut self.entities)
+    }
+}
+
+/// Applies the replayed commands without tracking them, the viewer synchronises nothing.
+pub struct UntrackedCommands;
+
+impl CommandTracker for UntrackedCommands {
+    fn apply_tracked(
+        &mut self,
+        command: ClientCommand,
+        world: &mut SubWorld,
+        entity: Entity,
+        map: &TileMap,
+        _command_frame: u32,
+    ) {
+        command.apply(world, entity, map);
+    }
+}
@@ -0,0 +1,54 @@
+/// How strongly the simulation speed reacts to the buffer being off target.
+const GAIN: f32 = 0.1;
+/// How far the simulation speed may deviate from normal speed.
+const MAX_DEVIATION: f32 = 0.25;
+/// How much of the new target speed is taken over per feedback message.
+const SMOOTHING: f32 = 0.3;
+
+/// Tunes the client simulation speed so its commands arrive a few frames before the server needs them.
+pub struct SimulationSpeedController {
+    target_buffer: i32,
+    buffered_frames: Option<i32>,
+    speed: f32,
+}
+
+impl SimulationSpeedController {
+    pub fn new(target_buffer: u32) -> SimulationSpeedController {
+        SimulationSpeedController {
+            target_buffer: target_buffer as i32,
+            buffered_frames: None,
+            speed: 1.0,
+        }
+    }
+
+    /// Handles the number of frames the commands are buffered on the server and returns the new simulation speed.
+    ///
+    /// A buffer below target means commands arrive too late and the client should run faster;
+    /// a buffer above target means the client runs too far ahead and should slow down.
+    pub fn update(&mut self, buffered_frames: i32) -> f32 {
+        self.buffered_frames = Some(buffered_frames);
+
+        let error = (self.target_buffer - buffered_frames) as f32;
+        let target_speed = 1.0 + (error * GAIN).max(-MAX_DEVIATION).min(MAX_DEVIATION);
+
+        self.speed += (target_speed - self.speed) * SMOOTHING;
+        self.speed
+    }
+
+    pub fn reset(&mut self) {
+        self.buffered_frames = None;
+        self.speed = 1.0;
+    }
+
+    pub fn target_buffer(&self) -> i32 {
+        self.target_buffer
+    }
+
+    pub fn buffered_frames(&self) -> Option<i32> {
+        self.buffered_frames
+    }
+
+    pub fn speed(&self) -> f32 {
+        self.speed
+    }
+}
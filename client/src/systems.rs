@@ -1,111 +1,210 @@
 use legion::prelude::{IntoQuery, *};
 use shared::{
-    calculate_player_movement,
-    components::{PlayerInfo, PlayerType, PlayerTypeOp, Position},
-    create_texture_from_text, get_rect_from_text,
+    checksum::WorldChecksum,
+    command::CommandQueue,
+    components::{Health, Pickup, PlayerInfo, PlayerType, PlayerTypeOp, Position, Score},
+    draw_entity, draw_health_bar, draw_map, draw_pickup,
+    map::TileMap,
     message::{ClientCommand, ClientMessage, ServerMessage},
-    ConnectionInformation, SCREEN_WIDTH,
+    replay::ReplayEvent,
+    snapshot::EntitySnapshot,
+    statistics::NetworkStatistics,
+    render_text, ConnectionInformation, ConnectionState, TextColumn, SCREEN_HEIGHT, SCREEN_WIDTH,
 };
 
 use legion_sync::{components::UidComponent, resources::EventResource};
+use tracing::{debug, info, warn};
 use net_sync::{
     synchronisation::{ClientCommandBuffer, CommandFrameTicker, ResimulationBuffer},
-    tracker::Trackable,
     transport,
-    transport::{ClientToServerMessage, PostBox, ServerToClientMessage},
+    transport::{ClientId, ClientToServerMessage, PostBox, ServerToClientMessage},
     uid::UidAllocator,
 };
 
-use sdl2::{keyboard::Keycode, pixels::Color, rect::Rect};
+use sdl2::{keyboard::Keycode, pixels::Color};
 use shared::systems::WindowResource;
-use std::collections::VecDeque;
 
+use crate::{
+    chat::ChatState, desync::DesyncDetector, input::command_for_key, replay_viewer::ViewerFrame,
+    simulation_speed::SimulationSpeedController,
+};
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+static PING_INTERVAL: Duration = Duration::from_secs(1);
+static SERVER_TIMEOUT: Duration = Duration::from_secs(5);
+
+const CHAT_FONT_SIZE: u16 = 18;
+const CHAT_LINE_HEIGHT: i32 = 24;
+
+/// Turns key presses into commands and queues them for the simulation, which predicts them.
 pub fn move_player_system() -> Box<dyn Schedulable> {
     SystemBuilder::new("move_player_system")
         .read_resource::<EventResource>()
         .read_resource::<CommandFrameTicker>()
-        .write_resource::<ClientCommandBuffer<ClientCommand>>()
+        .write_resource::<CommandQueue>()
         .write_resource::<PostBox<
             transport::ServerToClientMessage<ServerMessage>,
             transport::ClientToServerMessage<ClientMessage, ClientCommand>,
         >>()
-        .read_resource::<ConnectionInformation>()
+        .write_resource::<ConnectionInformation>()
         .write_resource::<PressedInputBuffer>()
-        .with_query(<(
-            legion::prelude::Write<Position>,
-            Read<UidComponent>,
-            Read<PlayerType>,
-            Read<PlayerInfo>,
-        )>::query())
-        .build(|command, mut world, resource, query| {
-            let event_resource = &resource.0;
-            let command_frame_ticker: &CommandFrameTicker = &resource.1;
-            let client_command_buffer: &mut ClientCommandBuffer<ClientCommand> = &mut resource.2;
-            let connection_info: &ConnectionInformation = &resource.4;
+        .write_resource::<NetworkStatistics>()
+        .read_resource::<ClientSettings>()
+        .with_query(<(Read<UidComponent>, Read<PlayerInfo>)>::query())
+        .build(|_, mut world, resource, query| {
+            let command_frame = resource.1.command_frame();
+            let command_queue: &mut CommandQueue = &mut resource.2;
+            let connection_info: &mut ConnectionInformation = &mut resource.4;
             let input_buffer: &mut PressedInputBuffer = &mut resource.5;
+            let statistics: &mut NetworkStatistics = &mut resource.6;
+            let settings: &ClientSettings = &resource.7;
 
             if let Some(key) = input_buffer.input.pop_front() {
                 let command = match key {
-                    Keycode::W => Some(ClientCommand::MoveUp),
-                    Keycode::D => Some(ClientCommand::MoveRight),
-                    Keycode::S => Some(ClientCommand::MoveDown),
-                    Keycode::A => Some(ClientCommand::MoveLeft),
                     Keycode::Q => {
-                        resource.3.send(ClientToServerMessage::Message(
-                            ClientMessage::ConnectionRequest,
-                        ));
+                        if *connection_info.connection_state() == ConnectionState::Disconnected {
+                            connection_info.set_connecting();
+                            resource.3.send(ClientToServerMessage::Message(
+                                ClientMessage::ConnectionRequest(settings.name.clone()),
+                            ));
+                        }
                         None
                     }
-                    _ => None,
-                };
-
-                for (mut pos, uid, player, info) in query.iter_mut(&mut world) {
-                    if info.client_id() == connection_info.client_id() {
-                        if let Some(command) = command.clone() {
-                            if let calculated_pos =
-                                calculate_player_movement(&command, pos.x, pos.y)
-                            {
-                                let mut pos = pos.client_track(
-                                    client_command_buffer,
-                                    command,
-                                    **uid,
-                                    command_frame_ticker.command_frame(),
-                                );
-                                pos.set(calculated_pos);
-                            }
+                    Keycode::Escape => {
+                        if connection_info.is_connected() {
+                            resource.3.send(ClientToServerMessage::Message(
+                                ClientMessage::Disconnect,
+                            ));
+                            connection_info
+                                .set_disconnecting_with_reason("You left the game.".to_string());
                         }
+                        None
                     }
+                    key => command_for_key(key),
+                };
+
+                if !connection_info.is_connected() {
+                    return;
+                }
+
+                let command = match command {
+                    Some(command) => command,
+                    None => return,
+                };
+
+                let client_id = connection_info.client_id();
+                let player = query
+                    .iter(&mut world)
+                    .find(|(_, info)| info.client_id() == client_id)
+                    .map(|(uid, _)| uid.uid());
+
+                if let Some(uid) = player {
+                    statistics.record_sent(bincode::serialized_size(&command).unwrap_or(0));
+                    statistics.record_command(command_frame);
+
+                    debug!(
+                        client_id = %client_id,
+                        command_frame,
+                        uid,
+                        ?command,
+                        "Predicted command"
+                    );
+
+                    command_queue.push(client_id, command_frame, command);
                 }
             }
         })
 }
 
+/// Handles the messages of the server after the handshake took its messages.
 pub fn handle_messages_system() -> Box<dyn Schedulable> {
     SystemBuilder::new("hand_messages_system")
         .write_resource::<PostBox<
             ServerToClientMessage<ServerMessage>,
             ClientToServerMessage<ClientMessage, ClientCommand>,
         >>()
-        .write_resource::<UidAllocator<Entity>>()
         .write_resource::<ConnectionInformation>()
         .write_resource::<CommandFrameTicker>()
-        .build(|command, _, resource, _| {
+        .write_resource::<NetworkStatistics>()
+        .write_resource::<SimulationSpeedController>()
+        .write_resource::<ChatState>()
+        .write_resource::<DesyncDetector>()
+        .build(|_, _, resource, _| {
             let postbox: &mut PostBox<
                 ServerToClientMessage<ServerMessage>,
                 ClientToServerMessage<ClientMessage, ClientCommand>,
             > = &mut resource.0;
-            let connection_info: &mut ConnectionInformation = &mut resource.2;
+            let connection_info: &mut ConnectionInformation = &mut resource.1;
+            let command_frame_ticker: &mut CommandFrameTicker = &mut resource.2;
+            let statistics: &mut NetworkStatistics = &mut resource.3;
+            let speed_controller: &mut SimulationSpeedController = &mut resource.4;
+            let chat: &mut ChatState = &mut resource.5;
+            let desync_detector: &mut DesyncDetector = &mut resource.6;
+
+            // The server can not close our connection, without a session its replication is dropped
+            // here instead of being applied by the synchronisation systems.
+            let in_session = match connection_info.connection_state() {
+                ConnectionState::Disconnected | ConnectionState::Disconnecting => false,
+                _ => true,
+            };
 
             let inbox = postbox.drain_inbox(|m| match m {
                 transport::ServerToClientMessage::Message(_) => true,
-                _ => false,
+                _ => !in_session,
             });
 
             for message in inbox {
+                statistics.record_received(bincode::serialized_size(&message).unwrap_or(0));
+
                 match message {
                     ServerToClientMessage::Message(message) => match message {
-                        ServerMessage::ConnectionAccepted(clientId) => {
-                            connection_info.set_connected(clientId);
+                        // Taken by the `client_handshake_system` before.
+                        ServerMessage::ConnectionAccepted(_)
+                        | ServerMessage::Map(_)
+                        | ServerMessage::WorldSnapshot(_) => {}
+                        ServerMessage::Pong {
+                            sequence,
+                            command_frame_offset,
+                            highest_seen,
+                            ..
+                        } => {
+                            statistics.finish_ping(sequence);
+                            statistics.set_command_frame_offset(command_frame_offset);
+                            statistics.acknowledge_commands(highest_seen);
+                        }
+                        ServerMessage::CommandRejected {
+                            command_frame,
+                            reason,
+                        } => {
+                            debug!(
+                                command_frame,
+                                ?reason,
+                                "Server rejected command"
+                            );
+                            statistics.record_rejected_command();
+                        }
+                        ServerMessage::CommandFrameFeedback { buffered_frames } => {
+                            let speed = speed_controller.update(buffered_frames);
+                            command_frame_ticker.adjust_simulation(speed);
+                        }
+                        ServerMessage::Kicked { reason }
+                        | ServerMessage::ServerShuttingDown { reason } => {
+                            info!(reason = %reason, "Disconnected by the server");
+                            connection_info.set_disconnecting_with_reason(reason);
+                        }
+                        ServerMessage::Chat { from, text } => {
+                            chat.receive(from, text);
+                        }
+                        ServerMessage::Ping(sequence) => {
+                            let message = ClientMessage::Pong(sequence);
+                            statistics.record_sent(bincode::serialized_size(&message).unwrap_or(0));
+                            postbox.send(ClientToServerMessage::Message(message));
+                        }
+                        ServerMessage::WorldChecksum(checksum) => {
+                            desync_detector.receive(checksum, connection_info.snapshot_frame());
                         }
                     },
                     _ => {}
@@ -114,23 +213,137 @@ pub fn handle_messages_system() -> Box<dyn Schedulable> {
         })
 }
 
-pub fn handle_resimulation() -> Box<dyn Schedulable> {
-    SystemBuilder::new("handle_resimulation")
-        .read_resource::<CommandFrameTicker>()
-        .write_resource::<ResimulationBuffer<ClientCommand>>()
-        .build(|command, mut world, resource, query| {
-            let mut resimulating: &mut ResimulationBuffer<ClientCommand> = &mut resource.1;
+/// Checks the local world against the last checksum from the server
+/// and reports entities that stay out of sync to the server.
+/// Tells the server about every correction that makes us resimulate, so replays can show them.
+///
+/// Runs before the simulation, which resimulates and clears the corrections.
+pub fn resimulation_report_system() -> Box<dyn Schedulable> {
+    SystemBuilder::new("resimulation_report_system")
+        .write_resource::<PostBox<
+            ServerToClientMessage<ServerMessage>,
+            ClientToServerMessage<ClientMessage, ClientCommand>,
+        >>()
+        .read_resource::<ResimulationBuffer<ClientCommand>>()
+        .read_resource::<ConnectionInformation>()
+        .write_resource::<NetworkStatistics>()
+        .build(|_, _, resource, _| {
+            let postbox: &mut PostBox<
+                ServerToClientMessage<ServerMessage>,
+                ClientToServerMessage<ClientMessage, ClientCommand>,
+            > = &mut resource.0;
+            let resimulating: &ResimulationBuffer<ClientCommand> = &resource.1;
+            let connection_info: &ConnectionInformation = &resource.2;
+            let statistics: &mut NetworkStatistics = &mut resource.3;
+
+            if !connection_info.is_connected() {
+                return;
+            }
 
             for entry in resimulating.iter() {
-                println!(
-                    "resimulating: from: {} to: {}, commands: {}",
-                    entry.start_command_frame,
-                    entry.end_command_frame,
-                    entry.to_resimmulate.len()
-                )
+                let message = ClientMessage::Resimulated {
+                    command_frame: entry.start_command_frame,
+                };
+                statistics.record_sent(bincode::serialized_size(&message).unwrap_or(0));
+                postbox.send(ClientToServerMessage::Message(message));
             }
+        })
+}
+
+pub fn desync_system() -> Box<dyn Schedulable> {
+    SystemBuilder::new("desync_system")
+        .write_resource::<PostBox<
+            ServerToClientMessage<ServerMessage>,
+            ClientToServerMessage<ClientMessage, ClientCommand>,
+        >>()
+        .write_resource::<DesyncDetector>()
+        .read_resource::<ConnectionInformation>()
+        .write_resource::<NetworkStatistics>()
+        .read_resource::<CommandFrameTicker>()
+        .with_query(<(
+            Read<UidComponent>,
+            TryRead<Position>,
+            TryRead<PlayerType>,
+            TryRead<PlayerInfo>,
+            TryRead<Health>,
+            TryRead<Pickup>,
+            TryRead<Score>,
+        )>::query())
+        .build(|_, mut world, resource, query| {
+            let postbox: &mut PostBox<
+                ServerToClientMessage<ServerMessage>,
+                ClientToServerMessage<ClientMessage, ClientCommand>,
+            > = &mut resource.0;
+            let desync_detector: &mut DesyncDetector = &mut resource.1;
+            let connection_info: &ConnectionInformation = &resource.2;
+            let statistics: &mut NetworkStatistics = &mut resource.3;
+            let current_frame = resource.4.command_frame();
+
+            if !connection_info.is_connected() {
+                return;
+            }
+
+            let client_id = connection_info.client_id();
+
+            let mut entities: Vec<EntitySnapshot> = query
+                .iter(&mut world)
+                .map(|(uid, pos, player_type, info, health, pickup, score)| {
+                    EntitySnapshot::new(
+                        uid.uid(),
+                        pos.as_deref(),
+                        player_type.as_deref(),
+                        info.as_deref(),
+                        health.as_deref(),
+                        pickup.as_deref(),
+                        score.as_deref(),
+                    )
+                })
+                .collect();
+
+            let player = entities
+                .iter()
+                .position(|entity| entity.client_id == Some(client_id));
+
+            if let Some(index) = player {
+                desync_detector.record_prediction(current_frame, entities[index].clone());
+            }
+
+            let server_checksum = match desync_detector.take_received() {
+                Some(checksum) => checksum,
+                None => return,
+            };
+
+            let command_frame = server_checksum.command_frame;
 
-            resimulating.entries.clear();
+            // Our player runs ahead of the server, compare our prediction for the checksum's frame.
+            if let Some(index) = player {
+                match desync_detector.prediction_at(command_frame) {
+                    Some(predicted) => entities[index] = predicted.clone(),
+                    None => {
+                        debug!(
+                            client_id = %client_id,
+                            command_frame,
+                            "No prediction for the checksum frame, skipped the desync check"
+                        );
+                        return;
+                    }
+                }
+            }
+
+            let uids = server_checksum.divergent(&WorldChecksum::new(command_frame, &entities));
+
+            if uids.is_empty() {
+                return;
+            }
+
+            warn!(client_id = %client_id, command_frame, ?uids, "Desync detected");
+
+            let message = ClientMessage::DesyncReport {
+                command_frame,
+                uids,
+            };
+            statistics.record_sent(bincode::serialized_size(&message).unwrap_or(0));
+            postbox.send(ClientToServerMessage::Message(message));
         })
 }
 
@@ -140,15 +353,28 @@ pub fn client_render_system() -> Box<dyn Schedulable> {
         .write_resource::<WindowResource>()
         .read_resource::<CommandFrameTicker>()
         .write_resource::<PressedInputBuffer>()
+        .read_resource::<NetworkStatistics>()
+        .write_resource::<NetworkOverlay>()
+        .read_resource::<SimulationSpeedController>()
+        .read_resource::<TileMap>()
+        .write_resource::<ChatState>()
         .with_query(<(
             Read<UidComponent>,
             legion::prelude::Read<Position>,
             Read<PlayerType>,
         )>::query())
-        .build(|_, mut world, resources, query| {
+        .with_query(<(Read<Position>, Read<Health>)>::query())
+        .with_query(<(Read<Position>, Read<Pickup>)>::query())
+        .with_query(<(Read<Score>, Read<PlayerInfo>)>::query())
+        .build(|_, mut world, resources, (query, health_query, pickup_query, score_query)| {
             let connection_info: &ConnectionInformation = &resources.0;
             let mut window_resource: &mut WindowResource = &mut resources.1;
             let input_buffer: &mut PressedInputBuffer = &mut resources.3;
+            let statistics: &NetworkStatistics = &resources.4;
+            let network_overlay: &mut NetworkOverlay = &mut resources.5;
+            let speed_controller: &SimulationSpeedController = &resources.6;
+            let map: &TileMap = &resources.7;
+            let chat: &mut ChatState = &mut resources.8;
             let command_frame = resources.2.command_frame();
             let simulation_speed = resources.2.simulation_speed();
 
@@ -163,87 +389,155 @@ pub fn client_render_system() -> Box<dyn Schedulable> {
             let texture_creator = canvas.texture_creator();
 
             // Load a font
-            let mut font = tff_context.load_font(assets, 50).unwrap();
+            let mut font = tff_context.load_font(&assets, 50).unwrap();
+            let chat_font = tff_context.load_font(&assets, CHAT_FONT_SIZE).unwrap();
 
             canvas.set_draw_color(Color::RGB(0, 0, 0));
             canvas.clear();
 
             if connection_info.is_connected() {
-                let command_frame_text = format!("CF: {}", command_frame);
-                let client_id_text = format!("C Id: {}", connection_info.client_id());
-                let simulation_speed_text = format!("Sim: {}", simulation_speed);
-
-                let rendered_text = create_texture_from_text(
-                    &texture_creator,
-                    &font,
-                    &command_frame_text,
-                    255,
-                    0,
-                    0,
-                );
-                canvas
-                    .copy(
-                        &rendered_text,
-                        None,
-                        Some(get_rect_from_text(
-                            &command_frame_text,
-                            SCREEN_WIDTH as i32 - 200,
-                            100,
-                        )),
-                    )
-                    .unwrap();
-
-                let rendered_text =
-                    create_texture_from_text(&texture_creator, &font, &client_id_text, 255, 0, 0);
-                canvas
-                    .copy(
-                        &rendered_text,
-                        None,
-                        Some(get_rect_from_text(
-                            &client_id_text,
-                            SCREEN_WIDTH as i32 - 200,
-                            120,
-                        )),
-                    )
-                    .unwrap();
-
-                let rendered_text =
-                    create_texture_from_text(&texture_creator, &font, &simulation_speed_text, 255, 0, 0);
-                canvas
-                    .copy(
-                        &rendered_text,
-                        None,
-                        Some(get_rect_from_text(
-                            &client_id_text,
-                            SCREEN_WIDTH as i32 - 200,
-                            160,
-                        )),
-                    )
-                    .unwrap();
+                draw_map(&mut canvas, map);
+
+                for (pos, pickup) in pickup_query.iter(&mut world) {
+                    draw_pickup(&mut canvas, pos.x, pos.y, &pickup);
+                }
 
+                {
+                    let mut scores: Vec<(ClientId, u32)> = score_query
+                        .iter(&mut world)
+                        .map(|(score, info)| (info.client_id(), score.points))
+                        .collect();
+                    scores.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+
+                    let mut scoreboard =
+                        TextColumn::new(&mut canvas, &texture_creator, &font, 10, 10)
+                            .with_color(255, 255, 255);
+                    scoreboard.line("Score");
+
+                    for (client_id, points) in scores {
+                        scoreboard.line(&format!("{}: {}", client_id, points));
+                    }
+                }
+
+                {
+                    let mut hud = TextColumn::new(
+                        &mut canvas,
+                        &texture_creator,
+                        &font,
+                        SCREEN_WIDTH as i32 - 200,
+                        100,
+                    );
+                    hud.line(&format!("CF: {}", command_frame));
+                    hud.line(&format!("C Id: {}", connection_info.client_id()));
+                    hud.line(&format!("Sim: {:.2}", simulation_speed));
+                    hud.line(&format!(
+                        "Buf: {}/{}",
+                        speed_controller
+                            .buffered_frames()
+                            .map_or("-".to_string(), |frames| frames.to_string()),
+                        speed_controller.target_buffer()
+                    ));
+
+                    if network_overlay.visible {
+                        let rtt = statistics
+                            .rtt()
+                            .map_or("-".to_string(), |rtt| rtt.as_millis().to_string());
+
+                        hud.skip(1);
+                        hud.line(&format!("RTT: {} ms", rtt));
+                        hud.line(&format!("In: {} B/s", statistics.bytes_in_per_second()));
+                        hud.line(&format!("Out: {} B/s", statistics.bytes_out_per_second()));
+                        hud.line(&format!(
+                            "Pkt: {}/{}",
+                            statistics.packets_in_per_second(),
+                            statistics.packets_out_per_second()
+                        ));
+                        hud.line(&format!("Offset: {}", statistics.command_frame_offset()));
+                        hud.line(&format!("Unacked: {}", statistics.unacknowledged_commands()));
+                        hud.line(&format!("Rejected: {}", statistics.rejected_commands()));
+                        hud.line(&format!(
+                            "Resim: {} ({})",
+                            statistics.resimulations(),
+                            statistics.resimulated_commands()
+                        ));
+                    }
+                }
 
                 // === Render Players
-                for (id, pos, player_type) in query.iter(&mut world) {
-                    if player_type.player_type() == PlayerTypeOp::Enemy {
-                        canvas.set_draw_color(Color::RGB(255, 0, 0));
-                        canvas.fill_rect(Rect::new(pos.x as i32, pos.y as i32, 50, 50));
-                    } else {
-                        canvas.set_draw_color(Color::RGB(0, 255, 0));
-                        canvas.fill_rect(Rect::new(pos.x as i32, pos.y as i32, 50, 50));
+                for (_, pos, player_type) in query.iter(&mut world) {
+                    draw_entity(&mut canvas, pos.x, pos.y, &player_type);
+                }
+
+                for (pos, health) in health_query.iter(&mut world) {
+                    draw_health_bar(&mut canvas, pos.x, pos.y, &health);
+                }
+            }
+
+            if !connection_info.is_connected() {
+                let mut status = TextColumn::new(&mut canvas, &texture_creator, &font, 10, 10)
+                    .with_color(255, 255, 255);
+
+                if *connection_info.connection_state() == ConnectionState::Synchronizing {
+                    status.line("Loading world...");
+                } else {
+                    if let Some(reason) = connection_info.disconnect_reason() {
+                        status.line(&format!("Disconnected: {}", reason));
                     }
+                    status.line("Press Q to connect");
+                }
+            }
+
+            // === Render Chat
+            if connection_info.is_connected() {
+                let white = Color::RGB(255, 255, 255);
+                let mut y_offset = SCREEN_HEIGHT as i32 + 10;
+
+                for (from, text) in chat.log() {
+                    let line = format!("{}: {}", from, text);
+                    render_text(&mut canvas, &texture_creator, &chat_font, &line, 10, y_offset, white);
+                    y_offset += CHAT_LINE_HEIGHT;
+                }
+
+                if chat.is_typing() {
+                    let line = format!("> {}_", chat.input());
+                    render_text(
+                        &mut canvas,
+                        &texture_creator,
+                        &chat_font,
+                        &line,
+                        10,
+                        y_offset,
+                        Color::RGB(255, 255, 0),
+                    );
                 }
             }
 
             // === Read input
             for event in event_pump.poll_iter() {
-                println!("event: {:?}", event);
+                if chat.is_typing() {
+                    match event {
+                        sdl2::event::Event::KeyDown { keycode, .. } => match keycode {
+                            Some(Keycode::Return) => chat.submit(),
+                            Some(Keycode::Escape) => chat.cancel(),
+                            Some(Keycode::Backspace) => chat.backspace(),
+                            _ => {}
+                        },
+                        sdl2::event::Event::TextInput { text, .. } => chat.type_text(&text),
+                        _ => {}
+                    }
+
+                    continue;
+                }
+
                 match event {
                     sdl2::event::Event::Quit { .. } => {}
                     sdl2::event::Event::KeyDown { keycode, .. } => match keycode {
                         Some(Keycode::W) | Some(Keycode::D) | Some(Keycode::S)
-                        | Some(Keycode::Q) | Some(Keycode::A) => {
+                        | Some(Keycode::Q) | Some(Keycode::A) | Some(Keycode::Escape) => {
                             input_buffer.input.push_back(keycode.unwrap())
                         }
+                        Some(Keycode::F1) => network_overlay.visible = !network_overlay.visible,
+                        Some(Keycode::Return) if connection_info.is_connected() => chat.open(),
                         _ => {}
                     },
                     _ => {}
@@ -254,6 +548,143 @@ pub fn client_render_system() -> Box<dyn Schedulable> {
         })
 }
 
+pub fn network_statistics_system() -> Box<dyn Schedulable> {
+    SystemBuilder::new("network_statistics_system")
+        .write_resource::<PostBox<
+            ServerToClientMessage<ServerMessage>,
+            ClientToServerMessage<ClientMessage, ClientCommand>,
+        >>()
+        .write_resource::<ConnectionInformation>()
+        .write_resource::<NetworkStatistics>()
+        .write_resource::<PingTimer>()
+        .build(|_, _, resource, _| {
+            let connection_info: &mut ConnectionInformation = &mut resource.1;
+            let statistics: &mut NetworkStatistics = &mut resource.2;
+            let ping_timer: &mut PingTimer = &mut resource.3;
+
+            statistics.update();
+
+            if connection_info.is_connected() && statistics.since_last_received() >= SERVER_TIMEOUT
+            {
+                connection_info
+                    .set_disconnecting_with_reason("Lost connection to the server.".to_string());
+                return;
+            }
+
+            if connection_info.is_connected() && ping_timer.last_ping.elapsed() >= PING_INTERVAL {
+                let message = ClientMessage::Ping(statistics.start_ping());
+                statistics.record_sent(bincode::serialized_size(&message).unwrap_or(0));
+                resource.0.send(ClientToServerMessage::Message(message));
+
+                ping_timer.last_ping = Instant::now();
+            }
+        })
+}
+
+/// Cleans up all session state once a session ended, so a reconnect starts from a clean world.
+pub fn session_system() -> Box<dyn Schedulable> {
+    SystemBuilder::new("session_system")
+        .write_resource::<ConnectionInformation>()
+        .write_resource::<UidAllocator<Entity>>()
+        .write_resource::<CommandFrameTicker>()
+        .write_resource::<ClientCommandBuffer<ClientCommand>>()
+        .write_resource::<ResimulationBuffer<ClientCommand>>()
+        .write_resource::<NetworkStatistics>()
+        .write_resource::<SimulationSpeedController>()
+        .write_resource::<PressedInputBuffer>()
+        .write_resource::<TileMap>()
+        .write_resource::<DesyncDetector>()
+        .with_query(<Read<UidComponent>>::query())
+        .build(|command, mut world, resource, query| {
+            let connection_info: &mut ConnectionInformation = &mut resource.0;
+
+            match connection_info.connection_state() {
+                ConnectionState::Disconnecting | ConnectionState::Disconnected => {}
+                _ => return,
+            }
+
+            // Remove everything the server replicated to us, also anything that was applied after
+            // the session ended and before its traffic was dropped.
+            let uid_allocator: &mut UidAllocator<Entity> = &mut resource.1;
+            for (entity, _) in query.iter_entities(&mut world) {
+                command.delete(entity);
+                uid_allocator.deallocate(entity);
+            }
+
+            if *connection_info.connection_state() != ConnectionState::Disconnecting {
+                return;
+            }
+
+            info!(
+                reason = connection_info.disconnect_reason().unwrap_or("-"),
+                "Session ended"
+            );
+
+            resource.2.reset();
+            resource.3.clear();
+            resource.4.entries.clear();
+            *resource.5 = NetworkStatistics::new();
+            resource.6.reset();
+            resource.7.input.clear();
+            *resource.8 = TileMap::new();
+            resource.9.reset();
+
+            connection_info.set_disconnected();
+        })
+}
+
+pub fn chat_system() -> Box<dyn Schedulable> {
+    SystemBuilder::new("chat_system")
+        .write_resource::<PostBox<
+            ServerToClientMessage<ServerMessage>,
+            ClientToServerMessage<ClientMessage, ClientCommand>,
+        >>()
+        .write_resource::<ChatState>()
+        .write_resource::<NetworkStatistics>()
+        .build(|_, _, resource, _| {
+            let chat: &mut ChatState = &mut resource.1;
+            let statistics: &mut NetworkStatistics = &mut resource.2;
+
+            for text in chat.drain_outgoing() {
+                let message = ClientMessage::Chat(text);
+                statistics.record_sent(bincode::serialized_size(&message).unwrap_or(0));
+                resource.0.send(ClientToServerMessage::Message(message));
+            }
+        })
+}
+
+pub struct NetworkOverlay {
+    pub visible: bool,
+}
+
+impl NetworkOverlay {
+    pub fn new() -> NetworkOverlay {
+        NetworkOverlay { visible: false }
+    }
+}
+
+pub struct PingTimer {
+    last_ping: Instant,
+}
+
+impl PingTimer {
+    pub fn new() -> PingTimer {
+        PingTimer {
+            last_ping: Instant::now(),
+        }
+    }
+}
+
+pub struct ClientSettings {
+    pub name: String,
+}
+
+impl ClientSettings {
+    pub fn new(name: String) -> ClientSettings {
+        ClientSettings { name }
+    }
+}
+
 pub struct PressedInputBuffer {
     pub input: VecDeque<Keycode>,
 }
@@ -265,3 +696,103 @@ impl PressedInputBuffer {
         }
     }
 }
+
+/// Spawns the snapshot and the connected players of the replay frame the viewer steps through,
+/// removes the players that left and queues the recorded commands.
+pub fn replay_viewer_events_system() -> Box<dyn Schedulable> {
+    SystemBuilder::new("replay_viewer_events_system")
+        .write_resource::<ViewerFrame>()
+        .write_resource::<CommandQueue>()
+        .with_query(<(Read<UidComponent>, TryRead<PlayerInfo>)>::query())
+        .build(|command, mut world, resource, query| {
+            let viewer_frame: &mut ViewerFrame = &mut resource.0;
+            let command_queue: &mut CommandQueue = &mut resource.1;
+
+            for entity in viewer_frame.snapshot.drain(..) {
+                entity.spawn(command);
+            }
+
+            for event in viewer_frame.events.drain(..) {
+                match event {
+                    ReplayEvent::Connected {
+                        client_id,
+                        uid,
+                        position,
+                        ..
+                    } => {
+                        // Spawns of the snapshot frame are already part of the snapshot.
+                        if query.iter(&mut world).any(|(other, _)| other.uid() == uid) {
+                            continue;
+                        }
+
+                        for (entity, (_, info)) in query.iter_entities(&mut world) {
+                            if info.map_or(false, |info| info.client_id() == client_id) {
+                                command.delete(entity);
+                            }
+                        }
+
+                        let health = Health::default();
+
+                        EntitySnapshot {
+                            uid,
+                            position: Some(position),
+                            player_type: Some(PlayerTypeOp::Player as u16),
+                            client_id: Some(client_id),
+                            health: Some((health.current, health.max)),
+                            pickup: None,
+                            score: Some(0),
+                        }
+                        .spawn(command);
+                    }
+                    ReplayEvent::Disconnected { client_id, .. } => {
+                        for (entity, (_, info)) in query.iter_entities(&mut world) {
+                            if info.map_or(false, |info| info.client_id() == client_id) {
+                                command.delete(entity);
+                            }
+                        }
+                    }
+                    ReplayEvent::Command {
+                        client_id,
+                        command: client_command,
+                        ..
+                    } => {
+                        if viewer_frame.apply_commands {
+                            command_queue.push(client_id, viewer_frame.frame, client_command);
+                        }
+                    }
+                    ReplayEvent::Snapshot(_) | ReplayEvent::Resimulated { .. } => {}
+                }
+            }
+        })
+}
+
+/// Collects the entities of the world the viewer reconstructs.
+pub fn replay_viewer_entities_system() -> Box<dyn Schedulable> {
+    SystemBuilder::new("replay_viewer_entities_system")
+        .write_resource::<ViewerFrame>()
+        .with_query(<(
+            Read<UidComponent>,
+            TryRead<Position>,
+            TryRead<PlayerType>,
+            TryRead<PlayerInfo>,
+            TryRead<Health>,
+            TryRead<Pickup>,
+            TryRead<Score>,
+        )>::query())
+        .build(|_, mut world, resource, query| {
+            resource.entities = query
+                .iter(&mut world)
+                .map(|(uid, pos, player_type, info, health, pickup, score)| {
+                    EntitySnapshot::new(
+                        uid.uid(),
+                        pos.as_deref(),
+                        player_type.as_deref(),
+                        info.as_deref(),
+                        health.as_deref(),
+                        pickup.as_deref(),
+                        score.as_deref(),
+                    )
+                })
+                .collect();
+        })
+}
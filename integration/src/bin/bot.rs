@@ -0,0 +1,186 @@
+use std::{
+    net::SocketAddr,
+    thread,
+    time::{Duration, Instant},
+};
+
+use integration::bot::{bot_world_builder, BotReport, BotSettings, Movement};
+use legion_sync::world::WorldBuilder;
+use net_sync::clock::{FrameLimiter, FrameRateLimitStrategy};
+
+const TICK_RATE: u32 = 30;
+
+struct Arguments {
+    bots: usize,
+    commands_per_second: f32,
+    duration: Duration,
+    address: SocketAddr,
+    movement: Movement,
+}
+
+static USAGE: &str = "Usage: bot [--bots <n>] [--rate <commands per second>] \
+                      [--duration <seconds>] [--address <address>] [--scripted]";
+
+fn main() {
+    let arguments = parse_arguments().unwrap_or_else(|e| {
+        eprintln!("{}\n{}", e, USAGE);
+        std::process::exit(1);
+    });
+
+    println!(
+        "Starting {} bots against {} for {} seconds, {} commands per second",
+        arguments.bots,
+        arguments.address,
+        arguments.duration.as_secs(),
+        arguments.commands_per_second
+    );
+
+    let bots: Vec<_> = (0..arguments.bots)
+        .map(|index| {
+            let settings = BotSettings {
+                name: format!("bot-{}", index),
+                movement: arguments.movement,
+                commands_per_second: arguments.commands_per_second,
+            };
+            let address = arguments.address;
+            let duration = arguments.duration;
+
+            // Every bot gets its own thread so a slow bot does not delay the others.
+            thread::spawn(move || run_bot(address, settings, duration))
+        })
+        .collect();
+
+    let reports: Vec<BotReport> = bots
+        .into_iter()
+        .map(|bot| bot.join().expect("Bot thread panicked"))
+        .collect();
+
+    print_summary(&reports);
+}
+
+fn run_bot(address: SocketAddr, settings: BotSettings, duration: Duration) -> BotReport {
+    let mut client = bot_world_builder(address, settings)
+        .with_resource(FrameLimiter::new(FrameRateLimitStrategy::Sleep, TICK_RATE))
+        .build();
+
+    let started = Instant::now();
+
+    while started.elapsed() < duration {
+        client.tick();
+
+        let resources = client.resources();
+
+        if resources
+            .get::<BotReport>()
+            .unwrap()
+            .disconnect_reason
+            .is_some()
+        {
+            break;
+        }
+
+        let mut limiter = resources.get_mut::<FrameLimiter>().unwrap();
+        limiter.wait();
+    }
+
+    let resources = client.resources();
+    let mut report = resources.get_mut::<BotReport>().unwrap();
+    std::mem::replace(&mut *report, BotReport::new())
+}
+
+fn print_summary(reports: &[BotReport]) {
+    let mut rtts: Vec<Duration> = reports
+        .iter()
+        .flat_map(|report| report.rtts.iter().cloned())
+        .collect();
+    rtts.sort();
+
+    let clients = reports.len().max(1) as u64;
+    let bytes_sent: u64 = reports.iter().map(|report| report.bytes_sent).sum();
+    let bytes_received: u64 = reports.iter().map(|report| report.bytes_received).sum();
+    let commands: u32 = reports.iter().map(|report| report.commands_sent).sum();
+    let corrections: u32 = reports.iter().map(|report| report.corrections).sum();
+    let tick_overruns = reports
+        .iter()
+        .map(|report| report.tick_overruns)
+        .max()
+        .unwrap_or(0);
+
+    println!("Bots: {}", reports.len());
+    println!(
+        "RTT p50: {} ms, p99: {} ms ({} samples)",
+        percentile(&rtts, 0.5).map_or("-".to_string(), |rtt| rtt.as_millis().to_string()),
+        percentile(&rtts, 0.99).map_or("-".to_string(), |rtt| rtt.as_millis().to_string()),
+        rtts.len()
+    );
+    println!(
+        "Message bytes per client: {} sent, {} received",
+        bytes_sent / clients,
+        bytes_received / clients
+    );
+    println!("Commands sent: {}, corrections: {}", commands, corrections);
+    println!("Server tick overruns: {}", tick_overruns);
+
+    for (index, report) in reports.iter().enumerate() {
+        if let Some(reason) = &report.disconnect_reason {
+            println!("bot-{} was disconnected: {}", index, reason);
+        }
+    }
+}
+
+/// Returns the value below which the given fraction of the sorted samples fall.
+fn percentile(sorted: &[Duration], fraction: f64) -> Option<Duration> {
+    if sorted.is_empty() {
+        return None;
+    }
+
+    let index = ((sorted.len() - 1) as f64 * fraction).round() as usize;
+    Some(sorted[index])
+}
+
+fn parse_arguments() -> Result<Arguments, String> {
+    let mut arguments = Arguments {
+        bots: 10,
+        commands_per_second: 10.0,
+        duration: Duration::from_secs(60),
+        address: "127.0.0.1:1119".parse().unwrap(),
+        movement: Movement::Random,
+    };
+
+    let mut args = std::env::args().skip(1);
+
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or_else(|| format!("Missing value for {}", arg));
+
+        match arg.as_str() {
+            "--bots" => arguments.bots = parse_argument(&value()?)?,
+            "--rate" => arguments.commands_per_second = parse_rate(&value()?)?,
+            "--duration" => arguments.duration = Duration::from_secs(parse_argument(&value()?)?),
+            "--address" => arguments.address = parse_argument(&value()?)?,
+            "--scripted" => arguments.movement = Movement::Scripted,
+            _ => return Err(format!("Unknown argument '{}'", arg)),
+        }
+    }
+
+    Ok(arguments)
+}
+
+/// The bot sends at most one command per tick, so faster rates can not be reached.
+fn parse_rate(argument: &str) -> Result<f32, String> {
+    let rate: f32 = parse_argument(argument)?;
+
+    if rate.is_finite() && rate > 0.0 && rate <= TICK_RATE as f32 {
+        Ok(rate)
+    } else {
+        Err(format!(
+            "Rate must be larger than 0 and at most {} commands per second",
+            TICK_RATE
+        ))
+    }
+}
+
+fn parse_argument<T: std::str::FromStr>(argument: &str) -> Result<T, String> {
+    argument
+        .parse()
+        .map_err(|_| format!("Invalid argument '{}'", argument))
+}
@@ -0,0 +1,253 @@
+use std::{
+    net::SocketAddr,
+    time::{Duration, Instant},
+};
+
+use legion::prelude::*;
+use legion::systems::schedule::Builder;
+use legion_sync::{
+    tracking::Bincode,
+    world::{client::ClientWorldBuilder, WorldBuilder},
+};
+use net_sync::{
+    compression::lz4::Lz4,
+    synchronisation::{CommandFrameTicker, ResimulationBuffer},
+    transport::{ClientToServerMessage, PostBox, ServerToClientMessage},
+};
+use rand::seq::SliceRandom;
+use shared::{
+    command::{CommandQueue, Move},
+    components::PlayerInfo,
+    map::TileMap,
+    message::{ClientCommand, ClientMessage, ServerMessage},
+    statistics::NetworkStatistics,
+    systems::{client_handshake_system, simulation_systems, SimulationRole},
+    ConnectionInformation, ConnectionState,
+};
+
+static PING_INTERVAL: Duration = Duration::from_secs(1);
+
+static MOVEMENT: [Move; 4] = [Move::Up, Move::Right, Move::Down, Move::Left];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Movement {
+    /// Pick a random direction for every command.
+    Random,
+    /// Walk in a square.
+    Scripted,
+}
+
+pub struct BotSettings {
+    pub name: String,
+    pub movement: Movement,
+    /// Movement commands sent per second, at most one per tick.
+    pub commands_per_second: f32,
+}
+
+/// Everything a bot measured during its run.
+pub struct BotReport {
+    pub rtts: Vec<Duration>,
+    pub corrections: u32,
+    pub commands_sent: u32,
+    /// Serialized size of the messages and commands the bot sent, without replication,
+    /// compression or transport framing.
+    pub bytes_sent: u64,
+    /// Serialized size of the messages the bot received, replication is not included.
+    pub bytes_received: u64,
+    /// The highest number of server tick overruns reported by the server.
+    pub tick_overruns: u32,
+    /// Set when the server kicked the bot or shut down.
+    pub disconnect_reason: Option<String>,
+}
+
+impl BotReport {
+    pub fn new() -> BotReport {
+        BotReport {
+            rtts: Vec::new(),
+            corrections: 0,
+            commands_sent: 0,
+            bytes_sent: 0,
+            bytes_received: 0,
+            tick_overruns: 0,
+            disconnect_reason: None,
+        }
+    }
+}
+
+struct BotState {
+    last_command: Instant,
+    last_ping: Instant,
+    script_step: usize,
+}
+
+/// A headless client that connects to the server and keeps sending movement commands.
+pub fn bot_world_builder(
+    address: SocketAddr,
+    settings: BotSettings,
+) -> ClientWorldBuilder<ServerMessage, ClientMessage, ClientCommand> {
+    ClientWorldBuilder::<ServerMessage, ClientMessage, ClientCommand>::default()
+        .with_tcp::<Bincode, Lz4>(address)
+        .register_systems(bot_systems)
+        .with_resource(ConnectionInformation::new())
+        .with_resource(NetworkStatistics::new())
+        .with_resource(BotReport::new())
+        .with_resource(CommandQueue::new())
+        .with_resource(TileMap::new())
+        .with_resource(BotState {
+            last_command: Instant::now(),
+            last_ping: Instant::now(),
+            script_step: 0,
+        })
+        .with_resource(settings)
+}
+
+fn bot_systems(builder: Builder) -> Builder {
+    // Corrections are counted before the simulation resimulates and clears them.
+    let builder = builder
+        .add_system(client_handshake_system())
+        .add_system(bot_messages_system())
+        .add_system(bot_command_system())
+        .add_system(bot_corrections_system());
+
+    simulation_systems(builder, SimulationRole::Client, |builder, _| builder).flush()
+}
+
+fn bot_messages_system() -> Box<dyn Schedulable> {
+    SystemBuilder::new("bot_messages_system")
+        .write_resource::<PostBox<
+            ServerToClientMessage<ServerMessage>,
+            ClientToServerMessage<ClientMessage, ClientCommand>,
+        >>()
+        .write_resource::<ConnectionInformation>()
+        .write_resource::<NetworkStatistics>()
+        .write_resource::<BotReport>()
+        .write_resource::<BotState>()
+        .read_resource::<BotSettings>()
+        .build(|_, _, resource, _| {
+            let postbox: &mut PostBox<
+                ServerToClientMessage<ServerMessage>,
+                ClientToServerMessage<ClientMessage, ClientCommand>,
+            > = &mut resource.0;
+            let connection_info: &mut ConnectionInformation = &mut resource.1;
+            let statistics: &mut NetworkStatistics = &mut resource.2;
+            let report: &mut BotReport = &mut resource.3;
+            let state: &mut BotState = &mut resource.4;
+            let settings: &BotSettings = &resource.5;
+
+            if *connection_info.connection_state() == ConnectionState::Disconnected
+                && report.disconnect_reason.is_none()
+            {
+                let message = ClientMessage::ConnectionRequest(settings.name.clone());
+                statistics.record_sent(bincode::serialized_size(&message).unwrap_or(0));
+                postbox.send(ClientToServerMessage::Message(message));
+                connection_info.set_connecting();
+            }
+
+            let inbox = postbox.drain_inbox(|message| match message {
+                ServerToClientMessage::Message(_) => true,
+                _ => false,
+            });
+
+            for message in inbox {
+                statistics.record_received(bincode::serialized_size(&message).unwrap_or(0));
+
+                if let ServerToClientMessage::Message(message) = message {
+                    match message {
+                        ServerMessage::Ping(sequence) => {
+                            let message = ClientMessage::Pong(sequence);
+                            statistics.record_sent(bincode::serialized_size(&message).unwrap_or(0));
+                            postbox.send(ClientToServerMessage::Message(message));
+                        }
+                        ServerMessage::Pong {
+                            sequence,
+                            tick_overruns,
+                            ..
+                        } => {
+                            if let Some(rtt) = statistics.finish_ping(sequence) {
+                                report.rtts.push(rtt);
+                            }
+                            report.tick_overruns = report.tick_overruns.max(tick_overruns);
+                        }
+                        ServerMessage::Kicked { reason }
+                        | ServerMessage::ServerShuttingDown { reason } => {
+                            report.disconnect_reason = Some(reason);
+                            connection_info.set_disconnected();
+                        }
+                        _ => {}
+                    }
+                }
+            }
+
+            if connection_info.is_connected() && state.last_ping.elapsed() >= PING_INTERVAL {
+                let message = ClientMessage::Ping(statistics.start_ping());
+                statistics.record_sent(bincode::serialized_size(&message).unwrap_or(0));
+                postbox.send(ClientToServerMessage::Message(message));
+                state.last_ping = Instant::now();
+            }
+
+            report.bytes_sent = statistics.total_bytes_out();
+            report.bytes_received = statistics.total_bytes_in();
+        })
+}
+
+/// Predicts and sends movement commands at the configured rate.
+fn bot_command_system() -> Box<dyn Schedulable> {
+    SystemBuilder::new("bot_command_system")
+        .read_resource::<CommandFrameTicker>()
+        .write_resource::<CommandQueue>()
+        .read_resource::<ConnectionInformation>()
+        .write_resource::<NetworkStatistics>()
+        .write_resource::<BotReport>()
+        .write_resource::<BotState>()
+        .read_resource::<BotSettings>()
+        .with_query(<Read<PlayerInfo>>::query())
+        .build(|_, mut world, resource, query| {
+            let command_frame = resource.0.command_frame();
+            let command_queue: &mut CommandQueue = &mut resource.1;
+            let connection_info: &ConnectionInformation = &resource.2;
+            let statistics: &mut NetworkStatistics = &mut resource.3;
+            let report: &mut BotReport = &mut resource.4;
+            let state: &mut BotState = &mut resource.5;
+            let settings: &BotSettings = &resource.6;
+
+            let interval = Duration::from_secs_f32(1.0 / settings.commands_per_second.max(0.01));
+
+            if !connection_info.is_connected() || state.last_command.elapsed() < interval {
+                return;
+            }
+
+            let client_id = connection_info.client_id();
+
+            let step = match settings.movement {
+                Movement::Random => *MOVEMENT.choose(&mut rand::thread_rng()).unwrap(),
+                Movement::Scripted => {
+                    // Five steps per side of the square.
+                    state.script_step += 1;
+                    MOVEMENT[(state.script_step / 5) % MOVEMENT.len()]
+                }
+            };
+            let client_command = ClientCommand::Move(step);
+
+            if query
+                .iter(&mut world)
+                .any(|info| info.client_id() == client_id)
+            {
+                statistics.record_sent(bincode::serialized_size(&client_command).unwrap_or(0));
+                report.commands_sent += 1;
+
+                command_queue.push(client_id, command_frame, client_command);
+            }
+
+            state.last_command = Instant::now();
+        })
+}
+
+/// Counts the server corrections that made the bot resimulate.
+fn bot_corrections_system() -> Box<dyn Schedulable> {
+    SystemBuilder::new("bot_corrections_system")
+        .read_resource::<ResimulationBuffer<ClientCommand>>()
+        .write_resource::<BotReport>()
+        .build(|_, _, resource, _| {
+            resource.1.corrections += resource.0.iter().count() as u32;
+        })
+}
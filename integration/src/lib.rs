@@ -0,0 +1,296 @@
+//! Headless server and client systems for running a server and several clients in one process.
+//!
+//! Both sides run the shared handshake, simulation, command policy and resimulation of the server
+//! and client binaries, without rendering, input or diagnostics, so tests can drive clients with
+//! scripted commands.
+
+pub mod bot;
+
+use std::{
+    collections::{HashMap, VecDeque},
+    net::{SocketAddr, TcpListener},
+};
+
+use legion::prelude::*;
+use legion::systems::schedule::Builder;
+use legion_sync::{
+    components::UidComponent,
+    tracking::Bincode,
+    world::{client::ClientWorldBuilder, server::ServerWorldBuilder, WorldBuilder},
+};
+use net_sync::{
+    compression::lz4::Lz4,
+    synchronisation::{CommandFrameTicker, ModifiedComponentsBuffer},
+    transport::{ClientToServerMessage, PostBox, PostOffice, ServerToClientMessage},
+    uid::UidAllocator,
+};
+use shared::{
+    command::CommandQueue,
+    command_policy::CommandPolicy,
+    components::{PlayerInfo, Position},
+    enemies::EnemySettings,
+    map::TileMap,
+    message::{ClientCommand, ClientMessage, ServerMessage},
+    pickups::PickupSettings,
+    players::PlayerSettings,
+    session::{accept_connection, SnapshotRequests},
+    statistics::NetworkStatistics,
+    systems::{client_handshake_system, simulation_systems, world_snapshot_system, SimulationRole},
+    ConnectionInformation, ConnectionState,
+};
+
+/// Spawn positions handed out to connecting clients in order, far enough apart to never collide.
+pub static SPAWN_POINTS: [(u16, u16); 4] = [(100, 100), (400, 100), (100, 300), (400, 300)];
+
+/// Commands a client sends, one per command frame once it is connected.
+pub struct Script {
+    commands: VecDeque<ClientCommand>,
+}
+
+impl Script {
+    pub fn new(commands: Vec<ClientCommand>) -> Script {
+        Script {
+            commands: commands.into_iter().collect(),
+        }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.commands.is_empty()
+    }
+}
+
+/// The `Position` of every player by uid, updated every tick.
+pub struct PositionReport {
+    pub positions: HashMap<u32, (u16, u16)>,
+}
+
+impl PositionReport {
+    pub fn new() -> PositionReport {
+        PositionReport {
+            positions: HashMap::new(),
+        }
+    }
+}
+
+/// A headless server listening on the given listener.
+///
+/// The server spawns neither enemies nor pickups, so the players only move by their commands.
+pub fn server_world_builder(
+    listener: TcpListener,
+) -> ServerWorldBuilder<ServerMessage, ClientMessage, ClientCommand> {
+    ServerWorldBuilder::<ServerMessage, ClientMessage, ClientCommand>::default()
+        .with_tcp::<Bincode, Lz4>(listener)
+        .register_systems(server_systems)
+        .with_resource(ModifiedComponentsBuffer::new())
+        .with_resource(CommandQueue::new())
+        .with_resource(CommandPolicy::default())
+        .with_resource(PositionReport::new())
+        .with_resource(SnapshotRequests::new())
+        .with_resource(TileMap::new())
+        .with_resource(EnemySettings::new(Vec::new()))
+        .with_resource(PlayerSettings::new(SPAWN_POINTS.to_vec()))
+        .with_resource(PickupSettings::new(1, 0))
+}
+
+/// A headless client that connects to the given address and plays the script.
+pub fn client_world_builder(
+    address: SocketAddr,
+    script: Script,
+) -> ClientWorldBuilder<ServerMessage, ClientMessage, ClientCommand> {
+    ClientWorldBuilder::<ServerMessage, ClientMessage, ClientCommand>::default()
+        .with_tcp::<Bincode, Lz4>(address)
+        .register_systems(client_systems)
+        .with_resource(ConnectionInformation::new())
+        .with_resource(CommandQueue::new())
+        .with_resource(NetworkStatistics::new())
+        .with_resource(PositionReport::new())
+        .with_resource(TileMap::new())
+        .with_resource(script)
+}
+
+fn server_systems(builder: Builder) -> Builder {
+    let builder = builder
+        .add_system(server_messages_system())
+        .add_system(server_commands_system());
+
+    simulation_systems(builder, SimulationRole::Server, |builder, _| builder)
+        .add_system(report_positions_system())
+        .flush()
+        .add_system(world_snapshot_system())
+}
+
+fn client_systems(builder: Builder) -> Builder {
+    let builder = builder
+        .add_system(client_handshake_system())
+        .add_system(client_messages_system())
+        .add_system(client_script_system());
+
+    simulation_systems(builder, SimulationRole::Client, |builder, _| builder)
+        .add_system(report_positions_system())
+        .flush()
+}
+
+fn server_messages_system() -> Box<dyn Schedulable> {
+    SystemBuilder::new("server_messages_system")
+        .write_resource::<PostOffice<ServerMessage, ClientMessage, ClientCommand>>()
+        .write_resource::<UidAllocator<Entity>>()
+        .write_resource::<PlayerSettings>()
+        .read_resource::<TileMap>()
+        .write_resource::<SnapshotRequests>()
+        .with_query(<Read<PlayerInfo>>::query())
+        .build(|command, mut world, resource, query| {
+            let postoffice: &mut PostOffice<ServerMessage, ClientMessage, ClientCommand> =
+                &mut resource.0;
+            let uid_allocator: &mut UidAllocator<Entity> = &mut resource.1;
+            let player_settings: &mut PlayerSettings = &mut resource.2;
+            let map: &TileMap = &resource.3;
+            let snapshot_requests: &mut SnapshotRequests = &mut resource.4;
+
+            for (_id, client) in postoffice.clients_with_inbox() {
+                let client_id = client.client_id();
+                let postbox = client.postbox_mut();
+
+                for message in postbox.drain_inbox(|_| true) {
+                    if let ClientMessage::ConnectionRequest(_) = message {
+                        let previous_players = query
+                            .iter_entities(&mut world)
+                            .filter(|(_, info)| info.client_id() == client_id)
+                            .map(|(entity, _)| entity)
+                            .collect();
+
+                        let accepted = accept_connection(
+                            command,
+                            uid_allocator,
+                            player_settings,
+                            map,
+                            snapshot_requests,
+                            client_id,
+                            previous_players,
+                        );
+
+                        for message in accepted.messages {
+                            postbox.send(ServerToClientMessage::Message(message));
+                        }
+                    }
+                }
+            }
+        })
+}
+
+fn server_commands_system() -> Box<dyn Schedulable> {
+    SystemBuilder::new("server_commands_system")
+        .write_resource::<PostOffice<ServerMessage, ClientMessage, ClientCommand>>()
+        .read_resource::<CommandFrameTicker>()
+        .write_resource::<CommandQueue>()
+        .read_resource::<CommandPolicy>()
+        .build(|_, _, resource, _| {
+            let postoffice: &mut PostOffice<ServerMessage, ClientMessage, ClientCommand> =
+                &mut resource.0;
+            let current_frame = resource.1.command_frame();
+            let command_queue: &mut CommandQueue = &mut resource.2;
+            let policy: &CommandPolicy = &resource.3;
+
+            for (client_id, client) in postoffice.clients_mut() {
+                let postbox = client.command_postbox_mut();
+                let highest_seen = postbox.highest_seen();
+
+                let due =
+                    policy.take_due_commands(*client_id, current_frame, highest_seen, |frame| {
+                        postbox
+                            .drain_frame(frame)
+                            .into_iter()
+                            .flatten()
+                            .map(|message| message.command)
+                            .collect()
+                    });
+
+                for (_, command) in due.apply {
+                    command_queue.push(*client_id, current_frame, command);
+                }
+
+                for (command_frame, reason) in due.rejected {
+                    client.postbox_mut().send(ServerToClientMessage::Message(
+                        ServerMessage::CommandRejected {
+                            command_frame,
+                            reason,
+                        },
+                    ));
+                }
+            }
+        })
+}
+
+fn client_messages_system() -> Box<dyn Schedulable> {
+    SystemBuilder::new("client_messages_system")
+        .write_resource::<PostBox<
+            ServerToClientMessage<ServerMessage>,
+            ClientToServerMessage<ClientMessage, ClientCommand>,
+        >>()
+        .write_resource::<ConnectionInformation>()
+        .build(|_, _, resource, _| {
+            let postbox: &mut PostBox<
+                ServerToClientMessage<ServerMessage>,
+                ClientToServerMessage<ClientMessage, ClientCommand>,
+            > = &mut resource.0;
+            let connection_info: &mut ConnectionInformation = &mut resource.1;
+
+            if *connection_info.connection_state() == ConnectionState::Disconnected {
+                connection_info.set_connecting();
+                postbox.send(ClientToServerMessage::Message(
+                    ClientMessage::ConnectionRequest("bot".to_string()),
+                ));
+            }
+
+            // The handshake took its messages, the rest is not needed by the tests.
+            postbox.drain_inbox(|message| match message {
+                ServerToClientMessage::Message(_) => true,
+                _ => false,
+            });
+        })
+}
+
+/// Queues one scripted command per command frame, the simulation predicts and sends it.
+fn client_script_system() -> Box<dyn Schedulable> {
+    SystemBuilder::new("client_script_system")
+        .read_resource::<CommandFrameTicker>()
+        .write_resource::<CommandQueue>()
+        .read_resource::<ConnectionInformation>()
+        .write_resource::<Script>()
+        .with_query(<Read<PlayerInfo>>::query())
+        .build(|_, mut world, resource, query| {
+            let command_frame = resource.0.command_frame();
+            let command_queue: &mut CommandQueue = &mut resource.1;
+            let connection_info: &ConnectionInformation = &resource.2;
+            let script: &mut Script = &mut resource.3;
+
+            if !connection_info.is_connected() {
+                return;
+            }
+
+            let client_id = connection_info.client_id();
+
+            // Wait until our player was replicated.
+            if !query
+                .iter(&mut world)
+                .any(|info| info.client_id() == client_id)
+            {
+                return;
+            }
+
+            if let Some(client_command) = script.commands.pop_front() {
+                command_queue.push(client_id, command_frame, client_command);
+            }
+        })
+}
+
+fn report_positions_system() -> Box<dyn Schedulable> {
+    SystemBuilder::new("report_positions_system")
+        .write_resource::<PositionReport>()
+        .with_query(<(Read<UidComponent>, Read<Position>, Read<PlayerInfo>)>::query())
+        .build(|_, mut world, resource, query| {
+            resource.positions = query
+                .iter(&mut world)
+                .map(|(uid, pos, _)| (uid.uid(), (pos.x, pos.y)))
+                .collect();
+        })
+}
@@ -0,0 +1,120 @@
+use std::{collections::HashMap, net::TcpListener, thread, time::Duration};
+
+use integration::{client_world_builder, server_world_builder, PositionReport, Script};
+use legion_sync::world::WorldBuilder;
+use shared::{command::Move, message::ClientCommand};
+
+static TICK_INTERVAL: Duration = Duration::from_millis(33);
+/// Give up when the scripts are not played within this many ticks.
+const MAX_TICKS: usize = 900;
+/// Ticks to run after the scripts finished, so all commands and corrections arrive.
+const SETTLE_TICKS: usize = 90;
+
+fn repeat(step: Move, times: usize) -> Vec<ClientCommand> {
+    vec![ClientCommand::Move(step); times]
+}
+
+/// Runs a server and one client per script until all scripts are played and the traffic settled,
+/// then returns the player positions seen by the server and by every client.
+fn run(
+    scripts: Vec<Vec<ClientCommand>>,
+) -> (HashMap<u32, (u16, u16)>, Vec<HashMap<u32, (u16, u16)>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+
+    let mut server = server_world_builder(listener).build();
+    let mut clients: Vec<_> = scripts
+        .into_iter()
+        .map(|script| client_world_builder(address, Script::new(script)).build())
+        .collect();
+
+    let mut ticks = 0;
+    let mut settle_ticks = 0;
+
+    while settle_ticks < SETTLE_TICKS {
+        server.tick();
+        for client in clients.iter_mut() {
+            client.tick();
+        }
+
+        thread::sleep(TICK_INTERVAL);
+        ticks += 1;
+
+        let finished = clients
+            .iter()
+            .all(|client| client.resources().get::<Script>().unwrap().is_finished());
+
+        if finished {
+            settle_ticks += 1;
+        } else {
+            assert!(ticks < MAX_TICKS, "Scripts were not played within {} ticks", MAX_TICKS);
+        }
+    }
+
+    let server_positions = server
+        .resources()
+        .get::<PositionReport>()
+        .unwrap()
+        .positions
+        .clone();
+
+    let client_positions = clients
+        .iter()
+        .map(|client| {
+            client
+                .resources()
+                .get::<PositionReport>()
+                .unwrap()
+                .positions
+                .clone()
+        })
+        .collect();
+
+    (server_positions, client_positions)
+}
+
+fn assert_converged(
+    server_positions: &HashMap<u32, (u16, u16)>,
+    client_positions: &[HashMap<u32, (u16, u16)>],
+    players: usize,
+) {
+    assert_eq!(
+        server_positions.len(),
+        players,
+        "The server does not have one player per client"
+    );
+
+    for (index, positions) in client_positions.iter().enumerate() {
+        assert_eq!(
+            positions, server_positions,
+            "Client {} did not converge to the server",
+            index
+        );
+    }
+}
+
+#[test]
+fn clients_converge_to_the_server() {
+    let scripts = vec![
+        [repeat(Move::Down, 5), repeat(Move::Right, 5)].concat(),
+        [repeat(Move::Left, 5), repeat(Move::Down, 3)].concat(),
+        [repeat(Move::Up, 4), repeat(Move::Right, 8)].concat(),
+    ];
+
+    let players = scripts.len();
+    let (server_positions, client_positions) = run(scripts);
+
+    assert_converged(&server_positions, &client_positions, players);
+}
+
+#[test]
+fn colliding_clients_converge_to_the_server() {
+    // The first two players spawn on the same row and walk into each other,
+    // so their predictions conflict with what the server applies.
+    let scripts = vec![repeat(Move::Right, 20), repeat(Move::Left, 20)];
+
+    let players = scripts.len();
+    let (server_positions, client_positions) = run(scripts);
+
+    assert_converged(&server_positions, &client_positions, players);
+}
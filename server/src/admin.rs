@@ -0,0 +1,139 @@
+use std::{collections::HashSet, io::BufRead, thread};
+
+use crossbeam_channel::Sender;
+use net_sync::transport::ClientId;
+
+use shared::enemies::MovementPattern;
+
+#[derive(Debug, Clone)]
+pub enum AdminCommand {
+    List,
+    Kick(ClientId),
+    Teleport(ClientId, u16, u16),
+    SpawnEnemy(u16, u16, MovementPattern),
+    SetTickRate(u32),
+    Pause,
+    Resume,
+    Step,
+    Quit(String),
+}
+
+impl AdminCommand {
+    pub fn parse(line: &str) -> Result<AdminCommand, String> {
+        let arguments: Vec<&str> = line.split_whitespace().collect();
+
+        match arguments.as_slice() {
+            ["list"] => Ok(AdminCommand::List),
+            ["kick", id] => Ok(AdminCommand::Kick(parse_argument(id)?)),
+            ["teleport", id, x, y] => Ok(AdminCommand::Teleport(
+                parse_argument(id)?,
+                parse_argument(x)?,
+                parse_argument(y)?,
+            )),
+            ["spawn", "enemy", x, y] => Ok(AdminCommand::SpawnEnemy(
+                parse_argument(x)?,
+                parse_argument(y)?,
+                MovementPattern::ChaseNearestPlayer,
+            )),
+            ["spawn", "enemy", x, y, "patrol", distance] => Ok(AdminCommand::SpawnEnemy(
+                parse_argument(x)?,
+                parse_argument(y)?,
+                MovementPattern::Patrol {
+                    distance: parse_argument(distance)?,
+                },
+            )),
+            ["set", "tickrate", rate] => match parse_argument(rate)? {
+                0 => Err("Tick rate must be larger than 0".to_string()),
+                rate => Ok(AdminCommand::SetTickRate(rate)),
+            },
+            ["pause"] => Ok(AdminCommand::Pause),
+            ["resume"] => Ok(AdminCommand::Resume),
+            ["step"] => Ok(AdminCommand::Step),
+            ["quit"] => Ok(AdminCommand::Quit(DEFAULT_SHUTDOWN_REASON.to_string())),
+            ["quit", reason @ ..] => Ok(AdminCommand::Quit(reason.join(" "))),
+            _ => Err(format!("Unknown command '{}'. {}", line.trim(), USAGE)),
+        }
+    }
+}
+
+static USAGE: &str = "Commands: list, kick <id>, teleport <id> <x> <y>, spawn enemy <x> <y> [patrol <distance>], set tickrate <n>, pause, resume, step, quit [reason]";
+
+pub static DEFAULT_SHUTDOWN_REASON: &str = "Server is shutting down.";
+
+fn parse_argument<T: std::str::FromStr>(argument: &str) -> Result<T, String> {
+    argument
+        .parse()
+        .map_err(|_| format!("Invalid argument '{}'", argument))
+}
+
+/// Reads admin commands from stdin on a separate thread so the server tick is never blocked.
+pub fn spawn_console(sender: Sender<AdminCommand>) {
+    thread::spawn(move || {
+        let stdin = std::io::stdin();
+
+        for line in stdin.lock().lines() {
+            let line = match line {
+                Ok(line) => line,
+                Err(_) => break,
+            };
+
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            match AdminCommand::parse(&line) {
+                Ok(command) => {
+                    if sender.send(command).is_err() {
+                        break;
+                    }
+                }
+                Err(e) => println!("{}", e),
+            }
+        }
+    });
+}
+
+/// Admin commands waiting to be applied by the `admin_console_system`.
+pub struct AdminCommandQueue {
+    commands: Vec<AdminCommand>,
+}
+
+impl AdminCommandQueue {
+    pub fn new() -> AdminCommandQueue {
+        AdminCommandQueue {
+            commands: Vec::new(),
+        }
+    }
+
+    pub fn push(&mut self, command: AdminCommand) {
+        self.commands.push(command);
+    }
+
+    pub fn drain(&mut self) -> Vec<AdminCommand> {
+        self.commands.drain(..).collect()
+    }
+}
+
+/// Clients kicked by the admin.
+///
+/// The post office can not close a single connection, so the server systems ignore everything
+/// a kicked client sends and send it nothing but the replication legion-sync does on its own.
+pub struct KickedClients {
+    clients: HashSet<ClientId>,
+}
+
+impl KickedClients {
+    pub fn new() -> KickedClients {
+        KickedClients {
+            clients: HashSet::new(),
+        }
+    }
+
+    pub fn kick(&mut self, client_id: ClientId) {
+        self.clients.insert(client_id);
+    }
+
+    pub fn contains(&self, client_id: ClientId) -> bool {
+        self.clients.contains(&client_id)
+    }
+}
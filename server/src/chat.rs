@@ -0,0 +1,84 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    time::{Duration, Instant},
+};
+
+use net_sync::transport::ClientId;
+use shared::MAX_CHAT_MESSAGE_LENGTH;
+
+pub enum ChatRejection {
+    Empty,
+    TooLong,
+    RateLimited,
+}
+
+impl ChatRejection {
+    pub fn reason(&self) -> String {
+        match self {
+            ChatRejection::Empty => "Message is empty.".to_string(),
+            ChatRejection::TooLong => format!(
+                "Message is longer than {} characters.",
+                MAX_CHAT_MESSAGE_LENGTH
+            ),
+            ChatRejection::RateLimited => "You are sending messages too fast.".to_string(),
+        }
+    }
+}
+
+/// Validates chat messages and limits how many messages a client may send in a time window.
+pub struct ChatModeration {
+    max_messages: usize,
+    window: Duration,
+    sent: HashMap<ClientId, VecDeque<Instant>>,
+}
+
+impl ChatModeration {
+    pub fn new(max_messages: usize, window: Duration) -> ChatModeration {
+        ChatModeration {
+            max_messages,
+            window,
+            sent: HashMap::new(),
+        }
+    }
+
+    /// Returns the trimmed message if the client is allowed to send it.
+    pub fn check(&mut self, client_id: ClientId, text: &str) -> Result<String, ChatRejection> {
+        let text = text.trim();
+
+        if text.is_empty() {
+            return Err(ChatRejection::Empty);
+        }
+
+        if text.chars().count() > MAX_CHAT_MESSAGE_LENGTH {
+            return Err(ChatRejection::TooLong);
+        }
+
+        let now = Instant::now();
+        let window = self.window;
+        let sent = self.sent.entry(client_id).or_insert_with(VecDeque::new);
+
+        while let Some(sent_at) = sent.front() {
+            if now.duration_since(*sent_at) < window {
+                break;
+            }
+            sent.pop_front();
+        }
+
+        if sent.len() >= self.max_messages {
+            return Err(ChatRejection::RateLimited);
+        }
+
+        sent.push_back(now);
+        Ok(text.to_string())
+    }
+
+    pub fn remove_client(&mut self, client_id: ClientId) {
+        self.sent.remove(&client_id);
+    }
+}
+
+impl Default for ChatModeration {
+    fn default() -> Self {
+        ChatModeration::new(5, Duration::from_secs(10))
+    }
+}
@@ -0,0 +1,114 @@
+use std::{collections::HashMap, time::Duration};
+
+use net_sync::transport::ClientId;
+use shared::statistics::NetworkStatistics;
+
+pub struct ClientDiagnostics {
+    pub name: String,
+    pub statistics: NetworkStatistics,
+    pub late_commands: u32,
+    pub early_commands: u32,
+    pub dropped_commands: u32,
+    /// The highest command frame received from the client so far.
+    pub highest_seen: u32,
+    /// How many frames ahead of the simulation the newest command arrived,
+    /// since the last feedback sent to the client.
+    pub buffered_frames: Option<i32>,
+}
+
+impl ClientDiagnostics {
+    pub fn new() -> ClientDiagnostics {
+        ClientDiagnostics {
+            name: String::from("-"),
+            statistics: NetworkStatistics::new(),
+            late_commands: 0,
+            early_commands: 0,
+            dropped_commands: 0,
+            highest_seen: 0,
+            buffered_frames: None,
+        }
+    }
+}
+
+/// Counts server ticks that took longer than the tick interval.
+pub struct TickStatistics {
+    pub tick_rate: u32,
+    pub overruns: u32,
+    pub last_tick: Duration,
+}
+
+impl TickStatistics {
+    pub fn new(tick_rate: u32) -> TickStatistics {
+        TickStatistics {
+            tick_rate,
+            overruns: 0,
+            last_tick: Duration::default(),
+        }
+    }
+
+    pub fn tick_interval(&self) -> Duration {
+        Duration::from_secs(1) / self.tick_rate.max(1)
+    }
+
+    pub fn record_tick(&mut self, duration: Duration) {
+        self.last_tick = duration;
+
+        if duration > self.tick_interval() {
+            self.overruns += 1;
+        }
+    }
+}
+
+pub struct ServerDiagnostics {
+    clients: HashMap<ClientId, ClientDiagnostics>,
+    page: usize,
+    /// Totals since the server started, these outlive the clients they were counted for.
+    pub commands_applied: u64,
+    pub late_commands: u64,
+    pub disconnects: u64,
+}
+
+impl ServerDiagnostics {
+    pub fn new() -> ServerDiagnostics {
+        ServerDiagnostics {
+            clients: HashMap::new(),
+            page: 0,
+            commands_applied: 0,
+            late_commands: 0,
+            disconnects: 0,
+        }
+    }
+
+    pub fn client_mut(&mut self, client_id: ClientId) -> &mut ClientDiagnostics {
+        self.clients
+            .entry(client_id)
+            .or_insert_with(ClientDiagnostics::new)
+    }
+
+    pub fn client(&self, client_id: ClientId) -> Option<&ClientDiagnostics> {
+        self.clients.get(&client_id)
+    }
+
+    pub fn remove_client(&mut self, client_id: ClientId) {
+        self.clients.remove(&client_id);
+    }
+
+    pub fn page(&self) -> usize {
+        self.page
+    }
+
+    pub fn next_page(&mut self, page_count: usize) {
+        if self.page + 1 < page_count {
+            self.page += 1;
+        }
+    }
+
+    pub fn previous_page(&mut self) {
+        self.page = self.page.saturating_sub(1);
+    }
+
+    /// Clamps the current page after clients have disconnected.
+    pub fn clamp_page(&mut self, page_count: usize) {
+        self.page = self.page.min(page_count.saturating_sub(1));
+    }
+}
@@ -1,9 +1,23 @@
-use std::net::{SocketAddr, TcpListener};
+use std::{
+    net::{SocketAddr, TcpListener},
+    path::PathBuf,
+    thread,
+    time::{Duration, Instant},
+};
 
 use legion::systems::schedule::Builder;
 use shared::{
+    command::CommandQueue,
+    command_policy::{CommandPolicy, EarlyCommandPolicy, LateCommandPolicy},
+    enemies::EnemySettings,
+    logging::initialize_logging,
+    map::TileMap,
     message::{ClientCommand, ClientMessage, ServerMessage},
-    systems::WindowResource,
+    pickups::PickupSettings,
+    players::PlayerSettings,
+    replay::{Replay, ReplayHeader},
+    session::SnapshotRequests,
+    systems::{simulation_systems, world_snapshot_system, SimulationRole, WindowResource},
 };
 
 use legion_sync::{
@@ -15,24 +29,261 @@ use net_sync::{
     compression::lz4::Lz4,
 };
 
-use crate::systems::{handle_commands_system, handle_messages_system, render_server};
-use net_sync::synchronisation::ModifiedComponentsBuffer;
+use crate::{
+    admin::{
+        spawn_console, AdminCommand, AdminCommandQueue, KickedClients, DEFAULT_SHUTDOWN_REASON,
+    },
+    chat::ChatModeration,
+    diagnostics::{ServerDiagnostics, TickStatistics},
+    metrics::Metrics,
+    profiling::{profile_system, TickProfiler, NETWORK_RECEIVE_STAGE},
+    replay::{ReplayPlayback, ReplayRecorder},
+    systems::{
+        admin_console_system, checksum_system, handle_commands_system, handle_messages_system,
+        metrics_system, ping_clients_system, render_server, replay_events_system,
+        replay_record_system, replay_verify_system, PingTimer,
+    },
+};
+use crossbeam_channel::unbounded;
+use tracing::info;
+use net_sync::{
+    synchronisation::{CommandFrameTicker, ModifiedComponentsBuffer},
+    transport::{PostOffice, ServerToClientMessage},
+};
 
+mod admin;
+mod chat;
+mod diagnostics;
+mod metrics;
+mod profiling;
+mod replay;
 mod systems;
 
+const TICK_RATE: u32 = 30;
+const PAUSED_POLL_INTERVAL: Duration = Duration::from_millis(50);
+const SHUTDOWN_FLUSH_TICKS: usize = 5;
+static USAGE: &str = "Usage: server [map] [--record <file>] [--replay <file>] [--profile] \
+[--profile-csv <file>] [--headless] [--metrics-port <port>] [--late-commands <apply|drop>] \
+[--early-commands <apply|drop>] [--max-frames-late <n>] [--max-frames-ahead <n>]";
+
+struct Arguments {
+    map: Option<PathBuf>,
+    record: Option<PathBuf>,
+    replay: Option<PathBuf>,
+    profile: bool,
+    profile_csv: Option<PathBuf>,
+    headless: bool,
+    metrics_port: Option<u16>,
+    command_policy: CommandPolicy,
+}
+
 fn main() {
     initialize_terminal();
 
-    let window = initialize_window();
+    let arguments = parse_arguments();
+
+    if let Some(path) = arguments.replay {
+        run_replay(path);
+        return;
+    }
+
+    let map = load_map(arguments.map);
+
+    let pickup_settings = PickupSettings::default();
+
+    let recorder = match arguments.record {
+        Some(path) => {
+            let header = ReplayHeader {
+                map: map.clone(),
+                tick_rate: TICK_RATE,
+                seed: pickup_settings.seed(),
+            };
+
+            info!("Recording replay to {}", path.display());
+            ReplayRecorder::create(&path, &header)
+                .unwrap_or_else(|e| panic!("Failed to create replay {}: {}", path.display(), e))
+        }
+        None => ReplayRecorder::disabled(),
+    };
+
+    let profiler = match arguments.profile_csv {
+        Some(path) => TickProfiler::with_csv(&path)
+            .unwrap_or_else(|e| panic!("Failed to create profile {}: {}", path.display(), e)),
+        None if arguments.profile => TickProfiler::enabled(),
+        None => TickProfiler::disabled(),
+    };
+
+    let metrics = match arguments.metrics_port {
+        Some(port) => {
+            info!("Serving metrics on http://127.0.0.1:{}/metrics", port);
+            Metrics::serve(port)
+                .unwrap_or_else(|e| panic!("Failed to serve metrics on port {}: {}", port, e))
+        }
+        None => Metrics::disabled(),
+    };
+
+    let systems = if arguments.headless {
+        initialize_headless_systems
+    } else {
+        initialize_systems
+    };
 
     let tcp_listener = TcpListener::bind("127.0.0.1:1119".parse::<SocketAddr>().unwrap()).unwrap();
 
+    let mut builder = ServerWorldBuilder::<ServerMessage, ClientMessage, ClientCommand>::default()
+        .with_tcp::<Bincode, Lz4>(tcp_listener)
+        .register_systems(systems)
+        .with_resource(FrameLimiter::new(FrameRateLimitStrategy::Yield, TICK_RATE))
+        .with_resource(ModifiedComponentsBuffer::new())
+        .with_resource(CommandQueue::new())
+        .with_resource(ServerDiagnostics::new())
+        .with_resource(PingTimer::new())
+        .with_resource(SnapshotRequests::new())
+        .with_resource(arguments.command_policy)
+        .with_resource(EnemySettings::for_map(&map))
+        .with_resource(PlayerSettings::for_map(&map))
+        .with_resource(pickup_settings)
+        .with_resource(ChatModeration::default())
+        .with_resource(AdminCommandQueue::new())
+        .with_resource(KickedClients::new())
+        .with_resource(recorder)
+        .with_resource(TickStatistics::new(TICK_RATE))
+        .with_resource(profiler)
+        .with_resource(metrics)
+        .with_resource(map);
+
+    if !arguments.headless {
+        builder = builder.with_resource(initialize_window());
+    }
+
+    let mut server = builder.build();
+
+    let (console_sender, console) = unbounded();
+    spawn_console(console_sender.clone());
+
+    ctrlc::set_handler(move || {
+        let _ = console_sender.send(AdminCommand::Quit(DEFAULT_SHUTDOWN_REASON.to_string()));
+    })
+    .expect("Failed to set Ctrl-C handler");
+
+    let mut paused = false;
+    // The server ticks as fast as it can until `set tickrate` asks for a fixed rate.
+    let mut limit_tick_rate = false;
+
+    let shutdown_reason = 'tick: loop {
+        let mut step = false;
+
+        for admin_command in console.try_iter() {
+            match admin_command {
+                AdminCommand::Pause => {
+                    paused = true;
+                    info!("Server paused");
+                }
+                AdminCommand::Resume => {
+                    paused = false;
+                    info!("Server resumed");
+                }
+                AdminCommand::Step => step = true,
+                AdminCommand::SetTickRate(rate) => {
+                    let resources = server.resources();
+                    *resources.get_mut::<FrameLimiter>().unwrap() =
+                        FrameLimiter::new(FrameRateLimitStrategy::Yield, rate);
+                    resources.get_mut::<TickStatistics>().unwrap().tick_rate = rate;
+                    limit_tick_rate = true;
+                    info!(rate, "Tick rate set");
+                }
+                AdminCommand::Quit(reason) => break 'tick reason,
+                world_command => {
+                    let resources = server.resources();
+                    let mut queue = resources.get_mut::<AdminCommandQueue>().unwrap();
+                    queue.push(world_command);
+                }
+            }
+        }
+
+        if paused && !step {
+            thread::sleep(PAUSED_POLL_INTERVAL);
+            continue;
+        }
+
+        server.resources().get_mut::<TickProfiler>().unwrap().begin_tick();
+
+        let tick_start = Instant::now();
+        server.tick();
+
+        let resources = server.resources();
+
+        resources
+            .get_mut::<TickStatistics>()
+            .unwrap()
+            .record_tick(tick_start.elapsed());
+        resources.get_mut::<TickProfiler>().unwrap().end_tick();
+
+        if limit_tick_rate {
+            let mut limiter = resources.get_mut::<FrameLimiter>().unwrap();
+            limiter.wait();
+        }
+    };
+
+    // Tell all clients the server is going away and give the network systems a few ticks to send it,
+    // the connections are closed when the server is dropped.
+    info!(reason = %shutdown_reason, "Shutting down");
+
+    {
+        let resources = server.resources();
+        let mut postoffice = resources
+            .get_mut::<PostOffice<ServerMessage, ClientMessage, ClientCommand>>()
+            .unwrap();
+
+        for (_, client) in postoffice.clients_mut() {
+            client.postbox_mut().send(ServerToClientMessage::Message(
+                ServerMessage::ServerShuttingDown {
+                    reason: shutdown_reason.clone(),
+                },
+            ));
+        }
+    }
+
+    for _ in 0..SHUTDOWN_FLUSH_TICKS {
+        server.tick();
+
+        let resources = server.resources();
+        let mut limiter = resources.get_mut::<FrameLimiter>().unwrap();
+        limiter.wait();
+    }
+}
+
+/// Re-runs the gameplay systems with the commands and connection events of a replay
+/// and checks the resulting positions against the recorded snapshots.
+fn run_replay(path: PathBuf) {
+    let replay = Replay::load(&path)
+        .unwrap_or_else(|e| panic!("Failed to load replay {}: {}", path.display(), e));
+
+    println!(
+        "Replaying {} events up to frame {} from {}",
+        replay.events.len(),
+        replay.last_frame(),
+        path.display()
+    );
+
+    let map = replay.header.map.clone();
+    let tick_rate = replay.header.tick_rate;
+    let seed = replay.header.seed;
+
+    // Nobody connects to a replay, the server world just needs a transport.
+    let tcp_listener = TcpListener::bind("127.0.0.1:0".parse::<SocketAddr>().unwrap()).unwrap();
+
     let mut server = ServerWorldBuilder::<ServerMessage, ClientMessage, ClientCommand>::default()
         .with_tcp::<Bincode, Lz4>(tcp_listener)
-        .register_systems(initialize_systems)
-        .with_resource(FrameLimiter::new(FrameRateLimitStrategy::Yield, 30))
-        .with_resource(window)
+        .register_systems(initialize_replay_systems)
+        .with_resource(FrameLimiter::new(FrameRateLimitStrategy::Yield, tick_rate))
         .with_resource(ModifiedComponentsBuffer::new())
+        .with_resource(CommandQueue::new())
+        .with_resource(EnemySettings::for_map(&map))
+        .with_resource(PlayerSettings::for_map(&map))
+        .with_resource(PickupSettings::default().with_seed(seed))
+        .with_resource(ReplayPlayback::new(replay))
+        .with_resource(map)
         .build();
 
     loop {
@@ -40,11 +291,100 @@ fn main() {
 
         let resources = server.resources();
 
-        // let mut limiter = resources.get_mut::<FrameLimiter>().unwrap();
-        // limiter.wait();
+        let command_frame = resources.get::<CommandFrameTicker>().unwrap().command_frame();
+        if resources
+            .get::<ReplayPlayback>()
+            .unwrap()
+            .is_finished(command_frame)
+        {
+            break;
+        }
+
+        let mut limiter = resources.get_mut::<FrameLimiter>().unwrap();
+        limiter.wait();
+    }
+
+    let resources = server.resources();
+    let playback = resources.get::<ReplayPlayback>().unwrap();
+
+    for mismatch in playback.mismatches.iter() {
+        println!("{}", mismatch);
+    }
+
+    let recorded_snapshots = playback.recorded_snapshots();
+
+    println!(
+        "Checked {} of {} snapshots, {} mismatches",
+        playback.checked_snapshots,
+        recorded_snapshots,
+        playback.mismatches.len()
+    );
+
+    if !playback.mismatches.is_empty() || playback.checked_snapshots != recorded_snapshots {
+        std::process::exit(1);
     }
 }
 
+fn parse_arguments() -> Arguments {
+    let mut arguments = Arguments {
+        map: None,
+        record: None,
+        replay: None,
+        profile: false,
+        profile_csv: None,
+        headless: false,
+        metrics_port: None,
+        command_policy: CommandPolicy::default(),
+    };
+
+    let mut args = std::env::args().skip(1);
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--record" => arguments.record = args.next().map(PathBuf::from),
+            "--replay" => arguments.replay = args.next().map(PathBuf::from),
+            "--profile" => arguments.profile = true,
+            "--profile-csv" => arguments.profile_csv = args.next().map(PathBuf::from),
+            "--headless" => arguments.headless = true,
+            "--metrics-port" => {
+                arguments.metrics_port = args.next().map(|port| {
+                    port.parse()
+                        .unwrap_or_else(|_| panic!("Invalid metrics port '{}'", port))
+                })
+            }
+            "--late-commands" => {
+                let policy = args.next().unwrap_or_default();
+                arguments.command_policy.late =
+                    LateCommandPolicy::parse(&policy).unwrap_or_else(|e| panic!("{}", e));
+            }
+            "--early-commands" => {
+                let policy = args.next().unwrap_or_default();
+                arguments.command_policy.early =
+                    EarlyCommandPolicy::parse(&policy).unwrap_or_else(|e| panic!("{}", e));
+            }
+            "--max-frames-late" => {
+                let frames = args.next().unwrap_or_default();
+                arguments.command_policy.max_frames_late = frames
+                    .parse()
+                    .unwrap_or_else(|_| panic!("Invalid number of frames '{}'", frames));
+            }
+            "--max-frames-ahead" => {
+                let frames = args.next().unwrap_or_default();
+                arguments.command_policy.max_frames_ahead = frames
+                    .parse()
+                    .unwrap_or_else(|_| panic!("Invalid number of frames '{}'", frames));
+            }
+            _ if arg.starts_with("--") => {
+                println!("Unknown argument '{}'. {}", arg, USAGE);
+                std::process::exit(1);
+            }
+            _ => arguments.map = Some(PathBuf::from(arg)),
+        }
+    }
+
+    arguments
+}
+
 fn initialize_window() -> WindowResource {
     let sdl_context = sdl2::init().unwrap();
     let video_subsystem = sdl_context.video().unwrap();
@@ -67,15 +407,75 @@ fn initialize_window() -> WindowResource {
     WindowResource::new(canvas, event_pump)
 }
 
+fn load_map(path: Option<PathBuf>) -> TileMap {
+    let path = match path {
+        Some(path) => path,
+        None => {
+            let mut assets = find_folder::Search::ParentsThenKids(3, 3)
+                .for_folder("assets")
+                .unwrap();
+            assets.push("map.txt");
+            assets
+        }
+    };
+
+    TileMap::load(&path)
+        .unwrap_or_else(|e| panic!("Failed to load map {}: {}", path.display(), e))
+}
+
 fn initialize_terminal() {
-    simple_logger::init().unwrap();
+    initialize_logging().unwrap_or_else(|e| panic!("Failed to initialize logging: {}", e));
 }
 
 fn initialize_systems(builder: Builder) -> Builder {
-    builder
-        .add_system(render_server())
-        //                .add_system(enemy_system())
+    initialize_gameplay_systems(
+        builder
+            .add_system(profile_system(NETWORK_RECEIVE_STAGE))
+            .add_system(render_server())
+            .add_system(profile_system("render_server")),
+    )
+}
+
+/// Runs without a window, so without rendering and the diagnostics overlay.
+fn initialize_headless_systems(builder: Builder) -> Builder {
+    initialize_gameplay_systems(builder.add_system(profile_system(NETWORK_RECEIVE_STAGE)))
+}
+
+/// Every system is followed by a profiling marker that records how long it took.
+fn initialize_gameplay_systems(builder: Builder) -> Builder {
+    let builder = builder
         .add_system(handle_messages_system())
+        .add_system(profile_system("handle_messages_system"))
         .add_system(handle_commands_system())
+        .add_system(profile_system("handle_commands_system"));
+
+    let builder = simulation_systems(builder, SimulationRole::Server, |builder, name| {
+        builder.add_system(profile_system(name))
+    });
+
+    builder
+        .add_system(ping_clients_system())
+        .add_system(profile_system("ping_clients_system"))
+        .add_system(checksum_system())
+        .add_system(profile_system("checksum_system"))
+        .add_system(admin_console_system())
+        .add_system(profile_system("admin_console_system"))
+        .add_system(replay_record_system())
+        .add_system(profile_system("replay_record_system"))
+        .add_system(metrics_system())
+        .add_system(profile_system("metrics_system"))
         .flush()
+        // Joining clients get the world including everything spawned this tick.
+        .add_system(world_snapshot_system())
+        .add_system(profile_system("world_snapshot_system"))
+}
+
+fn initialize_replay_systems(builder: Builder) -> Builder {
+    simulation_systems(
+        builder.add_system(replay_events_system()),
+        SimulationRole::Server,
+        |builder, _| builder,
+    )
+    .add_system(replay_verify_system())
+    .flush()
 }
@@ -0,0 +1,128 @@
+use std::{
+    fmt::Write as _,
+    io::{Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+use tracing::error;
+
+/// A scraper that does not send its request or read the response within this time is dropped,
+/// requests are answered one at a time.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Serves the server metrics in the Prometheus text format.
+///
+/// The metrics are rendered every tick by `metrics_system` and the listener thread
+/// answers every request with the last rendered text, so scraping never touches the world.
+pub struct Metrics {
+    exported: Option<Arc<Mutex<String>>>,
+}
+
+impl Metrics {
+    pub fn disabled() -> Metrics {
+        Metrics { exported: None }
+    }
+
+    /// Starts answering metric requests on the given local port.
+    pub fn serve(port: u16) -> Result<Metrics, String> {
+        let address = SocketAddr::from(([127, 0, 0, 1], port));
+        let listener = TcpListener::bind(address).map_err(|e| e.to_string())?;
+
+        let exported = Arc::new(Mutex::new(String::new()));
+        let shared = exported.clone();
+
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let result = stream
+                    .map_err(|e| e.to_string())
+                    .and_then(|stream| respond(stream, &shared));
+
+                if let Err(e) = result {
+                    error!("Failed to serve metrics: {}", e);
+                }
+            }
+        });
+
+        Ok(Metrics {
+            exported: Some(exported),
+        })
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.exported.is_some()
+    }
+
+    pub fn publish(&self, text: String) {
+        if let Some(exported) = &self.exported {
+            *exported.lock().unwrap() = text;
+        }
+    }
+}
+
+fn respond(mut stream: TcpStream, exported: &Mutex<String>) -> Result<(), String> {
+    stream
+        .set_read_timeout(Some(REQUEST_TIMEOUT))
+        .and_then(|_| stream.set_write_timeout(Some(REQUEST_TIMEOUT)))
+        .map_err(|e| e.to_string())?;
+
+    // Only the request line matters, the headers are ignored.
+    let mut request = [0; 1024];
+    let read = stream.read(&mut request).map_err(|e| e.to_string())?;
+    let request = String::from_utf8_lossy(&request[..read]);
+
+    let (status, body) = match request.split_whitespace().nth(1) {
+        Some("/metrics") | Some("/") => ("200 OK", exported.lock().unwrap().clone()),
+        _ => ("404 Not Found", "Not found\n".to_string()),
+    };
+
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\n\
+         Connection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    )
+    .map_err(|e| e.to_string())
+}
+
+/// Builds the metrics text, one `family` call per metric followed by its samples.
+pub struct MetricsText {
+    text: String,
+}
+
+impl MetricsText {
+    pub fn new() -> MetricsText {
+        MetricsText {
+            text: String::new(),
+        }
+    }
+
+    pub fn family(&mut self, name: &str, kind: &str, help: &str) -> &mut MetricsText {
+        let _ = writeln!(self.text, "# HELP {} {}", name, help);
+        let _ = writeln!(self.text, "# TYPE {} {}", name, kind);
+        self
+    }
+
+    pub fn sample<T: std::fmt::Display>(&mut self, name: &str, value: T) -> &mut MetricsText {
+        let _ = writeln!(self.text, "{} {}", name, value);
+        self
+    }
+
+    pub fn labeled_sample<T: std::fmt::Display>(
+        &mut self,
+        name: &str,
+        label: (&str, &str),
+        value: T,
+    ) -> &mut MetricsText {
+        let _ = writeln!(self.text, "{}{{{}=\"{}\"}} {}", name, label.0, label.1, value);
+        self
+    }
+
+    pub fn into_string(self) -> String {
+        self.text
+    }
+}
@@ -0,0 +1,190 @@
+use std::{
+    collections::VecDeque,
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
+    time::{Duration, Instant},
+};
+
+use legion::prelude::*;
+use tracing::error;
+
+/// Number of ticks the rolling averages and maxima are taken over.
+const PROFILE_WINDOW: usize = 100;
+/// Write the timings to the CSV file every this many ticks.
+const CSV_INTERVAL: u64 = 30;
+
+/// Time spent in the server tick before the first system ran, this is where the network is read.
+///
+/// Measured by a `profile_system` marker scheduled before all other systems.
+pub static NETWORK_RECEIVE_STAGE: &str = "network receive";
+/// Time spent in the server tick after the last system ran, this is where changes are flushed
+/// and sent to the clients.
+pub static NETWORK_SEND_STAGE: &str = "network send";
+
+pub struct StageTimings {
+    pub name: &'static str,
+    samples: VecDeque<Duration>,
+}
+
+impl StageTimings {
+    fn new(name: &'static str) -> StageTimings {
+        StageTimings {
+            name,
+            samples: VecDeque::with_capacity(PROFILE_WINDOW),
+        }
+    }
+
+    fn record(&mut self, duration: Duration) {
+        if self.samples.len() == PROFILE_WINDOW {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(duration);
+    }
+
+    pub fn last(&self) -> Duration {
+        self.samples.back().cloned().unwrap_or_default()
+    }
+
+    pub fn average(&self) -> Duration {
+        if self.samples.is_empty() {
+            return Duration::default();
+        }
+
+        self.samples.iter().sum::<Duration>() / self.samples.len() as u32
+    }
+
+    pub fn max(&self) -> Duration {
+        self.samples.iter().max().cloned().unwrap_or_default()
+    }
+}
+
+/// Times every scheduled system and the network stages of the server tick.
+///
+/// Systems are timed by `profile_system` markers scheduled after each of them,
+/// which measure the time since the previous marker.
+pub struct TickProfiler {
+    enabled: bool,
+    pub hud_visible: bool,
+    stages: Vec<StageTimings>,
+    last_mark: Option<Instant>,
+    ticks: u64,
+    csv: Option<BufWriter<File>>,
+}
+
+impl TickProfiler {
+    pub fn disabled() -> TickProfiler {
+        TickProfiler {
+            enabled: false,
+            hud_visible: false,
+            stages: Vec::new(),
+            last_mark: None,
+            ticks: 0,
+            csv: None,
+        }
+    }
+
+    pub fn enabled() -> TickProfiler {
+        TickProfiler {
+            enabled: true,
+            hud_visible: true,
+            ..TickProfiler::disabled()
+        }
+    }
+
+    /// Profiles and writes the rolling timings to the given CSV file.
+    pub fn with_csv<P: AsRef<Path>>(path: P) -> Result<TickProfiler, String> {
+        let mut csv = BufWriter::new(File::create(path).map_err(|e| e.to_string())?);
+        writeln!(csv, "tick,stage,last_us,average_us,max_us").map_err(|e| e.to_string())?;
+
+        Ok(TickProfiler {
+            csv: Some(csv),
+            ..TickProfiler::enabled()
+        })
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn stages(&self) -> &[StageTimings] {
+        &self.stages
+    }
+
+    pub fn begin_tick(&mut self) {
+        if self.enabled {
+            self.last_mark = Some(Instant::now());
+        }
+    }
+
+    /// Records the time since the previous mark for the given stage.
+    pub fn mark(&mut self, name: &'static str) {
+        if !self.enabled {
+            return;
+        }
+
+        let now = Instant::now();
+        let elapsed = self.last_mark.map_or(Duration::default(), |last| now - last);
+        self.last_mark = Some(now);
+
+        match self.stages.iter_mut().find(|stage| stage.name == name) {
+            Some(stage) => stage.record(elapsed),
+            None => {
+                let mut stage = StageTimings::new(name);
+                stage.record(elapsed);
+                self.stages.push(stage);
+            }
+        }
+    }
+
+    pub fn end_tick(&mut self) {
+        if !self.enabled {
+            return;
+        }
+
+        self.mark(NETWORK_SEND_STAGE);
+        self.ticks += 1;
+
+        if self.ticks % CSV_INTERVAL == 0 {
+            self.write_csv();
+        }
+    }
+
+    fn write_csv(&mut self) {
+        let ticks = self.ticks;
+        let csv = match &mut self.csv {
+            Some(csv) => csv,
+            None => return,
+        };
+
+        let mut result = Ok(());
+
+        for stage in self.stages.iter() {
+            result = result.and_then(|_| {
+                writeln!(
+                    csv,
+                    "{},{},{},{},{}",
+                    ticks,
+                    stage.name,
+                    stage.last().as_micros(),
+                    stage.average().as_micros(),
+                    stage.max().as_micros()
+                )
+            });
+        }
+
+        if let Err(e) = result.and_then(|_| csv.flush()) {
+            error!("Failed to write profile, CSV output stopped: {}", e);
+            self.csv = None;
+        }
+    }
+}
+
+/// Marks the end of the system scheduled right before it.
+pub fn profile_system(name: &'static str) -> Box<dyn Schedulable> {
+    SystemBuilder::new("profile_system")
+        .write_resource::<TickProfiler>()
+        .build(move |_, _, profiler, _| {
+            profiler.mark(name);
+        })
+}
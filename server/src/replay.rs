@@ -0,0 +1,105 @@
+use std::path::Path;
+
+use tracing::error;
+use shared::replay::{Replay, ReplayEvent, ReplayHeader, ReplayWriter};
+
+/// Take a world snapshot every this many command frames while recording.
+const DEFAULT_SNAPSHOT_INTERVAL: u32 = 10;
+
+/// Records commands, connection events and world snapshots to a replay file if recording is enabled.
+pub struct ReplayRecorder {
+    writer: Option<ReplayWriter>,
+    pub snapshot_interval: u32,
+}
+
+impl ReplayRecorder {
+    pub fn disabled() -> ReplayRecorder {
+        ReplayRecorder {
+            writer: None,
+            snapshot_interval: DEFAULT_SNAPSHOT_INTERVAL,
+        }
+    }
+
+    pub fn create<P: AsRef<Path>>(path: P, header: &ReplayHeader) -> Result<ReplayRecorder, String> {
+        Ok(ReplayRecorder {
+            writer: Some(ReplayWriter::create(path, header)?),
+            snapshot_interval: DEFAULT_SNAPSHOT_INTERVAL,
+        })
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.writer.is_some()
+    }
+
+    pub fn record(&mut self, event: ReplayEvent) {
+        if let Some(writer) = &mut self.writer {
+            if let Err(e) = writer.write(&event) {
+                error!("Failed to write replay, recording stopped: {}", e);
+                self.writer = None;
+            }
+        }
+    }
+
+    pub fn flush(&mut self) {
+        if let Some(writer) = &mut self.writer {
+            if let Err(e) = writer.flush() {
+                error!("Failed to write replay, recording stopped: {}", e);
+                self.writer = None;
+            }
+        }
+    }
+}
+
+/// State of an offline replay run.
+pub struct ReplayPlayback {
+    pub replay: Replay,
+    pub checked_snapshots: u32,
+    pub mismatches: Vec<String>,
+    /// The last frame that was played.
+    frame: Option<u32>,
+}
+
+impl ReplayPlayback {
+    pub fn new(replay: Replay) -> ReplayPlayback {
+        ReplayPlayback {
+            replay,
+            checked_snapshots: 0,
+            mismatches: Vec::new(),
+            frame: None,
+        }
+    }
+
+    /// Moves the playback to the given frame.
+    ///
+    /// Every recorded frame has to be played exactly once, a frame the ticker skipped or repeated
+    /// makes the replay diverge and is returned as an error.
+    pub fn advance(&mut self, frame: u32) -> Result<(), String> {
+        let expected = self.frame.map_or(frame, |last| last.wrapping_add(1));
+        self.frame = Some(frame);
+
+        if frame == expected {
+            Ok(())
+        } else {
+            Err(format!(
+                "Frame {}: expected to play frame {}, frames were skipped or repeated",
+                frame, expected
+            ))
+        }
+    }
+
+    /// The number of snapshots in the recording, a complete run checks all of them.
+    pub fn recorded_snapshots(&self) -> u32 {
+        self.replay
+            .events
+            .iter()
+            .filter(|event| match event {
+                ReplayEvent::Snapshot(_) => true,
+                _ => false,
+            })
+            .count() as u32
+    }
+
+    pub fn is_finished(&self, frame: u32) -> bool {
+        frame > self.replay.last_frame()
+    }
+}
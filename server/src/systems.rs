@@ -1,166 +1,910 @@
-use std::ops::DerefMut;
+use std::{collections::HashMap, ops::DerefMut};
 
 use legion::prelude::*;
 use shared::{
-    calculate_player_movement,
-    components::{PlayerInfo, PlayerType, PlayerTypeOp, Position},
-    create_texture_from_text, get_rect_from_text,
+    checksum::WorldChecksum,
+    command::CommandQueue,
+    command_policy::CommandPolicy,
+    components::{Health, Pickup, PlayerInfo, PlayerType, PlayerTypeOp, Position, Score},
+    draw_health_bar, draw_map, draw_pickup,
+    enemies::{Enemy, SpawnPoint},
+    map::TileMap,
     message::{ClientCommand, ClientMessage, ServerMessage},
-    SCREEN_WIDTH,
+    players::{sanitize_player_name, PlayerSettings},
+    render_text,
+    replay::ReplayEvent,
+    session::{accept_connection, spawn_player, SnapshotRequests},
+    snapshot::{EntitySnapshot, WorldSnapshot},
+    SCREEN_HEIGHT, SCREEN_WIDTH,
 };
 
-use legion_sync::{components::UidComponent, resources::EventResource};
-use log::debug;
+use legion_sync::components::UidComponent;
+use tracing::{debug, info, warn};
 use net_sync::{
+    clock::{FrameLimiter, FrameRateLimitStrategy},
     synchronisation::{CommandFrameTicker, ModifiedComponentsBuffer},
     tracker::Trackable,
     transport::{PostOffice, ServerToClientMessage},
     uid::UidAllocator,
 };
-use sdl2::{pixels::Color, rect::Rect};
+use sdl2::{
+    keyboard::Keycode,
+    pixels::Color,
+    rect::Rect,
+    render::{TextureCreator, WindowCanvas},
+    ttf::Font,
+    video::WindowContext,
+};
 use shared::systems::WindowResource;
+use std::time::{Duration, Instant};
 
-pub fn enemy_system() -> Box<dyn Schedulable> {
-    SystemBuilder::new("enemy_system")
-        .read_resource::<EventResource>()
-        .write_resource::<UidAllocator<Entity>>()
-        .read_resource::<CommandFrameTicker>()
-        .with_query(<(
-            legion::prelude::Write<Position>,
-            Read<UidComponent>,
-            Read<PlayerType>,
-        )>::query())
-        .build(|command, mut world, resource, query| {
-            if resource.2.command_frame() % 100 == 0 {
-                let entity = command
-                    .start_entity()
-                    .with_component(Position::new(20, 15))
-                    .with_component(PlayerType::new(PlayerTypeOp::Enemy))
-                    .build();
-
-                let component = UidComponent::new(resource.1.allocate(entity, None));
-                command.add_component(entity, component);
-            }
+use crate::{
+    admin::{AdminCommand, AdminCommandQueue, KickedClients},
+    chat::ChatModeration,
+    diagnostics::{ServerDiagnostics, TickStatistics},
+    metrics::{Metrics, MetricsText},
+    replay::{ReplayPlayback, ReplayRecorder},
+    profiling::TickProfiler,
+};
 
-            // if resource.2.command_frame() % 25 == 0 {
-            //     for (mut pos, id, player) in query.iter_mut(&mut world) {
-            //         if player.player_type() == PlayerTypeOp::Enemy {
-            //             let mut pos = pos.track(resource.0.notifier(), id.uid());
-            //
-            //             if pos.x == 0 {
-            //                 pos.x = 40;
-            //             } else {
-            //                 pos.x -= 1;
-            //             }
-            //         }
-            //     }
-            // }
-
-            if resource.2.command_frame() % 300 == 0 {
-                for (entity, (_, _, player)) in query.iter_entities_mut(&mut world) {
-                    if player.player_type() == PlayerTypeOp::Enemy {
-                        command.delete(entity);
-                    }
-                }
-            }
-        })
-}
+static PING_INTERVAL: Duration = Duration::from_secs(1);
+static SERVER_CHAT_NAME: &str = "server";
+/// Send clients a world checksum every this many command frames.
+const CHECKSUM_INTERVAL: u32 = 30;
+
+const DIAGNOSTICS_FONT_SIZE: u16 = 16;
+const DIAGNOSTICS_MARGIN: i32 = 10;
+const DIAGNOSTICS_ROW_HEIGHT: i32 = 22;
+const DIAGNOSTICS_ROWS_PER_PAGE: usize = 10;
+const METRICS_PLAYER_TYPES: [(&str, PlayerTypeOp); 2] = [
+    ("enemy", PlayerTypeOp::Enemy),
+    ("player", PlayerTypeOp::Player),
+];
+/// Left edge of the profiling overlay in the top right corner of the map.
+const PROFILE_HUD_X: i32 = SCREEN_WIDTH as i32 - 300;
+const DIAGNOSTICS_COLUMNS: [(&str, i32); 9] = [
+    ("Id", 40),
+    ("Address", 130),
+    ("Name", 100),
+    ("RTT", 70),
+    ("Offset", 60),
+    ("Highest", 70),
+    ("Late", 80),
+    ("Early/Drop", 90),
+    ("In/Out B/s", 130),
+];
 
 pub fn handle_messages_system() -> Box<dyn Schedulable> {
     SystemBuilder::new("hand_messages_system")
         .write_resource::<PostOffice<ServerMessage, ClientMessage, ClientCommand>>()
         .write_resource::<UidAllocator<Entity>>()
-        .build(|command, _, resource, _| {
+        .write_resource::<ServerDiagnostics>()
+        .write_resource::<PlayerSettings>()
+        .read_resource::<TileMap>()
+        .write_resource::<ChatModeration>()
+        .read_resource::<CommandFrameTicker>()
+        .write_resource::<ReplayRecorder>()
+        .read_resource::<TickStatistics>()
+        .write_resource::<SnapshotRequests>()
+        .read_resource::<KickedClients>()
+        .with_query(<Read<PlayerInfo>>::query())
+        .build(|command, mut world, resource, query| {
             let postoffice: &mut PostOffice<ServerMessage, ClientMessage, ClientCommand> =
                 &mut resource.0;
             let entity_builder: &mut UidAllocator<Entity> = &mut resource.1;
+            let diagnostics: &mut ServerDiagnostics = &mut resource.2;
+            let player_settings: &mut PlayerSettings = &mut resource.3;
+            let map: &TileMap = &resource.4;
+            let chat_moderation: &mut ChatModeration = &mut resource.5;
+            let command_frame = resource.6.command_frame();
+            let recorder: &mut ReplayRecorder = &mut resource.7;
+            let tick_overruns = resource.8.overruns;
+            let snapshot_requests: &mut SnapshotRequests = &mut resource.9;
+            let kicked: &KickedClients = &resource.10;
+
+            let mut chat_messages = Vec::new();
+            let mut disconnected = Vec::new();
 
             for (_id, client) in postoffice.clients_with_inbox() {
                 let client_id = client.client_id();
+                let command_frame_offset = client.command_postbox().command_frame_offset();
+                let highest_seen = client.command_postbox().highest_seen();
                 let postbox = client.postbox_mut();
 
+                if kicked.contains(client_id) {
+                    postbox.drain_inbox(|_| true);
+                    continue;
+                }
+
+                let client_diagnostics = diagnostics.client_mut(client_id);
+
                 for message in postbox.drain_inbox(|_| true) {
+                    client_diagnostics
+                        .statistics
+                        .record_received(bincode::serialized_size(&message).unwrap_or(0));
+
                     match message {
-                        ClientMessage::ConnectionRequest => {
-                            debug!("Connection Request");
+                        ClientMessage::ConnectionRequest(name) => {
+                            client_diagnostics.name = sanitize_player_name(&name, client_id);
 
-                            let builder = command
-                                .start_entity()
-                                .with_component(Position { x: 200, y: 200 })
-                                .with_component(PlayerType::new(PlayerTypeOp::Player))
-                                .with_component(PlayerInfo::new(client_id));
+                            let previous_players = query
+                                .iter_entities(&mut world)
+                                .filter(|(_, info)| info.client_id() == client_id)
+                                .map(|(entity, _)| entity)
+                                .collect();
 
-                            let entity = builder.build();
+                            let accepted = accept_connection(
+                                command,
+                                entity_builder,
+                                player_settings,
+                                map,
+                                snapshot_requests,
+                                client_id,
+                                previous_players,
+                            );
 
-                            let id = entity_builder.allocate(entity, None);
+                            info!(
+                                client_id = %client_id,
+                                uid = accepted.uid,
+                                command_frame,
+                                name = %client_diagnostics.name,
+                                "Client connected"
+                            );
 
-                            let uid = UidComponent::new(id);
-                            command.add_component(entity, uid);
+                            recorder.record(ReplayEvent::Connected {
+                                frame: command_frame,
+                                client_id,
+                                uid: accepted.uid,
+                                position: accepted.position,
+                            });
 
-                            postbox.send(ServerToClientMessage::Message(
-                                ServerMessage::ConnectionAccepted(client_id),
-                            ));
+                            for message in accepted.messages {
+                                postbox.send(ServerToClientMessage::Message(message));
+                            }
                         }
                         ClientMessage::Disconnect => {
-                            debug!("Disconnect Request");
-                            // remove player
+                            info!(client_id = %client_id, command_frame, "Client disconnected");
+
+                            for (entity, info) in query.iter_entities(&mut world) {
+                                if info.client_id() == client_id {
+                                    command.delete(entity);
+                                    entity_builder.deallocate(entity);
+                                }
+                            }
+
+                            chat_moderation.remove_client(client_id);
+                            disconnected.push(client_id);
+
+                            recorder.record(ReplayEvent::Disconnected {
+                                frame: command_frame,
+                                client_id,
+                            });
+                        }
+                        ClientMessage::Ping(sequence) => {
+                            let message = ServerMessage::Pong {
+                                sequence,
+                                command_frame_offset,
+                                highest_seen,
+                                tick_overruns,
+                            };
+                            client_diagnostics
+                                .statistics
+                                .record_sent(bincode::serialized_size(&message).unwrap_or(0));
+                            postbox.send(ServerToClientMessage::Message(message));
+                        }
+                        ClientMessage::Pong(sequence) => {
+                            client_diagnostics.statistics.finish_ping(sequence);
+                        }
+                        ClientMessage::Chat(text) => match chat_moderation.check(client_id, &text) {
+                            Ok(text) => {
+                                chat_messages.push(ServerMessage::Chat {
+                                    from: client_diagnostics.name.clone(),
+                                    text,
+                                });
+                            }
+                            Err(rejection) => {
+                                debug!(client_id = %client_id, "Rejected chat message");
+                                postbox.send(ServerToClientMessage::Message(ServerMessage::Chat {
+                                    from: SERVER_CHAT_NAME.to_string(),
+                                    text: rejection.reason(),
+                                }));
+                            }
+                        },
+                        ClientMessage::DesyncReport {
+                            command_frame,
+                            uids,
+                        } => {
+                            warn!(
+                                client_id = %client_id,
+                                command_frame,
+                                ?uids,
+                                "Client reported a desync"
+                            );
+                        }
+                        ClientMessage::Resimulated {
+                            command_frame: resimulated_frame,
+                        } => {
+                            debug!(
+                                client_id = %client_id,
+                                command_frame,
+                                resimulated_frame,
+                                "Client resimulated"
+                            );
+
+                            recorder.record(ReplayEvent::Resimulated {
+                                frame: command_frame,
+                                client_id,
+                                command_frame: resimulated_frame,
+                            });
                         }
                     }
                 }
             }
+
+            for client_id in disconnected {
+                diagnostics.remove_client(client_id);
+                diagnostics.disconnects += 1;
+            }
+
+            for message in chat_messages {
+                for (_, client) in postoffice
+                    .clients_mut()
+                    .filter(|(id, _)| !kicked.contains(**id))
+                {
+                    client
+                        .postbox_mut()
+                        .send(ServerToClientMessage::Message(message.clone()));
+                }
+            }
         })
 }
 
+/// Takes the commands due this frame from the clients and queues them for the simulation.
 pub fn handle_commands_system() -> Box<dyn Schedulable> {
     SystemBuilder::new("player_move_system")
-        .with_query(<(
-            legion::prelude::Write<Position>,
-            Read<PlayerInfo>,
-            Read<UidComponent>,
-        )>::query())
         .write_resource::<PostOffice<ServerMessage, ClientMessage, ClientCommand>>()
         .read_resource::<CommandFrameTicker>()
-        .write_resource::<ModifiedComponentsBuffer>()
-        .build(|_, world, resource, query| {
+        .write_resource::<CommandQueue>()
+        .write_resource::<ServerDiagnostics>()
+        .read_resource::<CommandPolicy>()
+        .write_resource::<ReplayRecorder>()
+        .read_resource::<KickedClients>()
+        .build(|_, _, resource, _| {
             let postoffice: &mut PostOffice<ServerMessage, ClientMessage, ClientCommand> =
                 &mut resource.0;
             let command_frame_ticker: &CommandFrameTicker = &resource.1;
-            let modified_components_buffer: &mut ModifiedComponentsBuffer = &mut resource.2;
+            let command_queue: &mut CommandQueue = &mut resource.2;
+            let diagnostics: &mut ServerDiagnostics = &mut resource.3;
+            let policy: &CommandPolicy = &resource.4;
+            let recorder: &mut ReplayRecorder = &mut resource.5;
+            let kicked: &KickedClients = &resource.6;
+
+            let current_frame = command_frame_ticker.command_frame();
+            // Client, frame the command was sent for and the command.
+            let mut to_apply = Vec::new();
+            let mut late_commands = 0;
 
             for (client_id, client) in postoffice.clients_mut() {
                 let postbox = client.command_postbox_mut();
-                let messages = postbox.drain_frame(command_frame_ticker.command_frame());
+                let highest_seen = postbox.highest_seen();
+
+                let due =
+                    policy.take_due_commands(*client_id, current_frame, highest_seen, |frame| {
+                        postbox
+                            .drain_frame(frame)
+                            .into_iter()
+                            .flatten()
+                            .map(|message| message.command)
+                            .collect()
+                    });
+
+                // Drained all the same, so the commands of kicked clients don't pile up.
+                if kicked.contains(*client_id) {
+                    continue;
+                }
+
+                let client_diagnostics = diagnostics.client_mut(*client_id);
+
+                client_diagnostics.late_commands += due.late;
+                client_diagnostics.early_commands += due.early;
+                client_diagnostics.dropped_commands += due.dropped;
+                late_commands += due.late as u64;
+
+                for (sent_frame, command) in due.apply {
+                    to_apply.push((*client_id, sent_frame, command));
+                }
+
+                for (command_frame, reason) in due.rejected {
+                    client.postbox_mut().send(ServerToClientMessage::Message(
+                        ServerMessage::CommandRejected {
+                            command_frame,
+                            reason,
+                        },
+                    ));
+                }
+            }
+
+            diagnostics.late_commands += late_commands;
+            diagnostics.commands_applied += to_apply.len() as u64;
+
+            for (client_id, sent_frame, command) in to_apply {
+                diagnostics
+                    .client_mut(client_id)
+                    .statistics
+                    .record_received(bincode::serialized_size(&command).unwrap_or(0));
+
+                recorder.record(ReplayEvent::Command {
+                    frame: current_frame,
+                    command_frame: current_frame,
+                    sent_frame,
+                    client_id,
+                    command: command.clone(),
+                });
+
+                debug!(
+                    client_id = %client_id,
+                    command_frame = sent_frame,
+                    current_frame,
+                    ?command,
+                    "Applying command"
+                );
+
+                command_queue.push(client_id, current_frame, command);
+            }
+        })
+}
+
+pub fn ping_clients_system() -> Box<dyn Schedulable> {
+    SystemBuilder::new("ping_clients_system")
+        .write_resource::<PostOffice<ServerMessage, ClientMessage, ClientCommand>>()
+        .write_resource::<ServerDiagnostics>()
+        .write_resource::<PingTimer>()
+        .read_resource::<CommandFrameTicker>()
+        .read_resource::<KickedClients>()
+        .build(|_, _, resource, _| {
+            let postoffice: &mut PostOffice<ServerMessage, ClientMessage, ClientCommand> =
+                &mut resource.0;
+            let diagnostics: &mut ServerDiagnostics = &mut resource.1;
+            let ping_timer: &mut PingTimer = &mut resource.2;
+            let command_frame_ticker: &CommandFrameTicker = &resource.3;
+            let kicked: &KickedClients = &resource.4;
+
+            let should_ping = ping_timer.last_ping.elapsed() >= PING_INTERVAL;
+
+            for (client_id, client) in postoffice
+                .clients_mut()
+                .filter(|(id, _)| !kicked.contains(**id))
+            {
+                let client_diagnostics = diagnostics.client_mut(*client_id);
+                client_diagnostics.statistics.update();
+
+                // How many frames ahead of the simulation the newest command arrived,
+                // measured on the tick it shows up, so idle clients don't skew it.
+                let highest_seen = client.command_postbox().highest_seen();
+                if highest_seen > client_diagnostics.highest_seen {
+                    client_diagnostics.highest_seen = highest_seen;
+                    client_diagnostics.buffered_frames = Some(
+                        highest_seen as i32 - command_frame_ticker.command_frame() as i32,
+                    );
+                }
+
+                if should_ping {
+                    let mut messages = vec![ServerMessage::Ping(
+                        client_diagnostics.statistics.start_ping(),
+                    )];
+
+                    // Clients that sent no commands since the last feedback get none.
+                    if let Some(buffered_frames) = client_diagnostics.buffered_frames.take() {
+                        messages.push(ServerMessage::CommandFrameFeedback { buffered_frames });
+                    }
+
+                    let statistics = &mut client_diagnostics.statistics;
 
-                if let Some(messages) = messages {
                     for message in messages {
-                        match message.command {
-                            ClientCommand::MoveUp
-                            | ClientCommand::MoveRight
-                            | ClientCommand::MoveDown
-                            | ClientCommand::MoveLeft => {
-                                debug!("Handling Move Command: {:?}", message.command);
-
-                                for (mut pos, player_info, uid) in query.iter_mut(world) {
-                                    let mut pos = pos.server_track(
-                                        modified_components_buffer,
-                                        **uid,
-                                        command_frame_ticker.command_frame(),
-                                    );
-
-                                    if player_info.client_id() == *client_id {
-                                        let new_pos = calculate_player_movement(
-                                            &message.command,
-                                            pos.x,
-                                            pos.y,
-                                        );
-                                        pos.deref_mut().set(new_pos);
-                                    }
-                                }
+                        statistics.record_sent(bincode::serialized_size(&message).unwrap_or(0));
+                        client
+                            .postbox_mut()
+                            .send(ServerToClientMessage::Message(message));
+                    }
+                }
+            }
+
+            if should_ping {
+                ping_timer.last_ping = Instant::now();
+            }
+        })
+}
+
+pub fn metrics_system() -> Box<dyn Schedulable> {
+    SystemBuilder::new("metrics_system")
+        .read_resource::<Metrics>()
+        .read_resource::<PostOffice<ServerMessage, ClientMessage, ClientCommand>>()
+        .read_resource::<CommandFrameTicker>()
+        .read_resource::<ServerDiagnostics>()
+        .read_resource::<TickStatistics>()
+        .read_resource::<KickedClients>()
+        .with_query(<Read<PlayerType>>::query())
+        .build(|_, mut world, resource, query| {
+            let metrics: &Metrics = &resource.0;
+            let postoffice: &PostOffice<ServerMessage, ClientMessage, ClientCommand> =
+                &resource.1;
+            let command_frame = resource.2.command_frame();
+            let diagnostics: &ServerDiagnostics = &resource.3;
+            let tick_statistics: &TickStatistics = &resource.4;
+            let kicked: &KickedClients = &resource.5;
+
+            if !metrics.is_enabled() {
+                return;
+            }
+
+            let mut clients: Vec<_> = postoffice
+                .clients()
+                .map(|(id, _)| *id)
+                .filter(|id| !kicked.contains(*id))
+                .collect();
+            clients.sort();
+
+            let mut text = MetricsText::new();
+
+            text.family("server_connected_clients", "gauge", "Connected clients.")
+                .sample("server_connected_clients", clients.len());
+            text.family("server_command_frame", "gauge", "Current command frame.")
+                .sample("server_command_frame", command_frame);
+            text.family(
+                "server_tick_duration_seconds",
+                "gauge",
+                "Duration of the last server tick.",
+            )
+            .sample(
+                "server_tick_duration_seconds",
+                tick_statistics.last_tick.as_secs_f64(),
+            );
+            text.family(
+                "server_tick_overruns_total",
+                "counter",
+                "Ticks that took longer than the tick interval.",
+            )
+            .sample("server_tick_overruns_total", tick_statistics.overruns);
+
+            text.family(
+                "server_client_message_bytes_sent_total",
+                "counter",
+                "Serialized bytes of messages sent to a client, replication excluded.",
+            );
+            for client_id in clients.iter() {
+                if let Some(client) = diagnostics.client(*client_id) {
+                    text.labeled_sample(
+                        "server_client_message_bytes_sent_total",
+                        ("client_id", &client_id.to_string()),
+                        client.statistics.total_bytes_out(),
+                    );
+                }
+            }
+
+            text.family(
+                "server_client_message_bytes_received_total",
+                "counter",
+                "Serialized bytes of messages and commands received from a client.",
+            );
+            for client_id in clients.iter() {
+                if let Some(client) = diagnostics.client(*client_id) {
+                    text.labeled_sample(
+                        "server_client_message_bytes_received_total",
+                        ("client_id", &client_id.to_string()),
+                        client.statistics.total_bytes_in(),
+                    );
+                }
+            }
+
+            text.family("server_entities", "gauge", "Entities per player type.");
+            for (label, player_type) in METRICS_PLAYER_TYPES.iter() {
+                let count = query
+                    .iter(&mut world)
+                    .filter(|entity| entity.player_type() == *player_type)
+                    .count();
+                text.labeled_sample("server_entities", ("player_type", *label), count);
+            }
+
+            text.family(
+                "server_commands_applied_total",
+                "counter",
+                "Client commands applied to the world.",
+            )
+            .sample("server_commands_applied_total", diagnostics.commands_applied);
+            text.family(
+                "server_late_commands_total",
+                "counter",
+                "Client commands that arrived after their frame was simulated.",
+            )
+            .sample("server_late_commands_total", diagnostics.late_commands);
+            text.family(
+                "server_disconnects_total",
+                "counter",
+                "Clients that disconnected or were kicked.",
+            )
+            .sample("server_disconnects_total", diagnostics.disconnects);
+
+            metrics.publish(text.into_string());
+        })
+}
+
+/// Sends every client a checksum of all synchronized entities, so it can detect desyncs.
+pub fn checksum_system() -> Box<dyn Schedulable> {
+    SystemBuilder::new("checksum_system")
+        .write_resource::<PostOffice<ServerMessage, ClientMessage, ClientCommand>>()
+        .read_resource::<CommandFrameTicker>()
+        .write_resource::<ServerDiagnostics>()
+        .read_resource::<KickedClients>()
+        .with_query(<(
+            Read<UidComponent>,
+            TryRead<Position>,
+            TryRead<PlayerType>,
+            TryRead<PlayerInfo>,
+            TryRead<Health>,
+            TryRead<Pickup>,
+            TryRead<Score>,
+        )>::query())
+        .build(|_, mut world, resource, query| {
+            let postoffice: &mut PostOffice<ServerMessage, ClientMessage, ClientCommand> =
+                &mut resource.0;
+            let command_frame = resource.1.command_frame();
+            let diagnostics: &mut ServerDiagnostics = &mut resource.2;
+            let kicked: &KickedClients = &resource.3;
+
+            if command_frame % CHECKSUM_INTERVAL != 0 {
+                return;
+            }
+
+            let entities: Vec<EntitySnapshot> = query
+                .iter(&mut world)
+                .map(|(uid, pos, player_type, info, health, pickup, score)| {
+                    EntitySnapshot::new(
+                        uid.uid(),
+                        pos.as_deref(),
+                        player_type.as_deref(),
+                        info.as_deref(),
+                        health.as_deref(),
+                        pickup.as_deref(),
+                        score.as_deref(),
+                    )
+                })
+                .collect();
+
+            let message =
+                ServerMessage::WorldChecksum(WorldChecksum::new(command_frame, &entities));
+
+            for (client_id, client) in postoffice
+                .clients_mut()
+                .filter(|(id, _)| !kicked.contains(**id))
+            {
+                diagnostics
+                    .client_mut(*client_id)
+                    .statistics
+                    .record_sent(bincode::serialized_size(&message).unwrap_or(0));
+                client
+                    .postbox_mut()
+                    .send(ServerToClientMessage::Message(message.clone()));
+            }
+        })
+}
+
+pub fn admin_console_system() -> Box<dyn Schedulable> {
+    SystemBuilder::new("admin_console_system")
+        .write_resource::<AdminCommandQueue>()
+        .write_resource::<PostOffice<ServerMessage, ClientMessage, ClientCommand>>()
+        .write_resource::<UidAllocator<Entity>>()
+        .read_resource::<CommandFrameTicker>()
+        .write_resource::<ModifiedComponentsBuffer>()
+        .write_resource::<ServerDiagnostics>()
+        .write_resource::<ReplayRecorder>()
+        .write_resource::<ChatModeration>()
+        .write_resource::<KickedClients>()
+        .with_query(<(
+            legion::prelude::Write<Position>,
+            Read<UidComponent>,
+            Read<PlayerInfo>,
+        )>::query())
+        .with_query(<Read<PlayerType>>::query())
+        .build(|command, mut world, resource, (player_query, type_query)| {
+            let postoffice: &mut PostOffice<ServerMessage, ClientMessage, ClientCommand> =
+                &mut resource.1;
+            let uid_allocator: &mut UidAllocator<Entity> = &mut resource.2;
+            let command_frame = resource.3.command_frame();
+            let modified_components_buffer: &mut ModifiedComponentsBuffer = &mut resource.4;
+            let diagnostics: &mut ServerDiagnostics = &mut resource.5;
+            let recorder: &mut ReplayRecorder = &mut resource.6;
+            let chat_moderation: &mut ChatModeration = &mut resource.7;
+            let kicked: &mut KickedClients = &mut resource.8;
+
+            for admin_command in resource.0.drain() {
+                match admin_command {
+                    AdminCommand::List => {
+                        println!("Command frame: {}", command_frame);
+
+                        for (client_id, client) in
+                            postoffice.clients().filter(|(id, _)| !kicked.contains(**id))
+                        {
+                            let name = diagnostics
+                                .client(*client_id)
+                                .map_or("-".to_string(), |client| client.name.clone());
+                            let position = player_query
+                                .iter(&mut world)
+                                .find(|(_, _, info)| info.client_id() == *client_id)
+                                .map_or("-".to_string(), |(pos, _, _)| {
+                                    format!("({}, {})", pos.x, pos.y)
+                                });
+
+                            println!(
+                                "Client {} {} '{}' at {}",
+                                client_id,
+                                client.addr(),
+                                name,
+                                position
+                            );
+                        }
+
+                        let enemies = type_query
+                            .iter(&mut world)
+                            .filter(|player_type| player_type.player_type() == PlayerTypeOp::Enemy)
+                            .count();
+                        println!("Enemies: {}", enemies);
+                    }
+                    AdminCommand::Kick(client_id) => {
+                        if kicked.contains(client_id) {
+                            warn!(client_id = %client_id, "Client was already kicked");
+                            continue;
+                        }
+
+                        match postoffice.clients_mut().find(|(id, _)| **id == client_id) {
+                            Some((_, client)) => {
+                                client.postbox_mut().send(ServerToClientMessage::Message(
+                                    ServerMessage::Kicked {
+                                        reason: "Kicked by the server admin.".to_string(),
+                                    },
+                                ));
+                            }
+                            None => {
+                                warn!(client_id = %client_id, "No client to kick");
+                                continue;
+                            }
+                        }
+
+                        for (entity, (_, _, info)) in player_query.iter_entities_mut(&mut world) {
+                            if info.client_id() == client_id {
+                                command.delete(entity);
+                                uid_allocator.deallocate(entity);
+                            }
+                        }
+
+                        // The post office can not close a single connection, the server ignores the
+                        // client from now on and the client ends its session on `Kicked`.
+                        kicked.kick(client_id);
+                        recorder.record(ReplayEvent::Disconnected {
+                            frame: command_frame,
+                            client_id,
+                        });
+                        chat_moderation.remove_client(client_id);
+                        diagnostics.remove_client(client_id);
+                        diagnostics.disconnects += 1;
+
+                        info!(client_id = %client_id, command_frame, "Kicked client");
+                    }
+                    AdminCommand::Teleport(client_id, x, y) => {
+                        let mut found = false;
+
+                        for (mut pos, uid, info) in player_query.iter_mut(&mut world) {
+                            if info.client_id() == client_id {
+                                let mut pos =
+                                    pos.server_track(modified_components_buffer, **uid, command_frame);
+                                pos.deref_mut().set((x, y));
+                                found = true;
                             }
                         }
+
+                        if found {
+                            info!(client_id = %client_id, x, y, "Teleported client");
+                        } else {
+                            warn!(client_id = %client_id, "No player to teleport");
+                        }
+                    }
+                    AdminCommand::SpawnEnemy(x, y, pattern) => {
+                        let spawn_point = SpawnPoint::new(x, y, pattern);
+
+                        let entity = command
+                            .start_entity()
+                            .with_component(Position::new(x, y))
+                            .with_component(PlayerType::new(PlayerTypeOp::Enemy))
+                            .with_component(Enemy::new(&spawn_point, command_frame))
+                            .build();
+
+                        let component = UidComponent::new(uid_allocator.allocate(entity, None));
+                        command.add_component(entity, component);
+
+                        info!(x, y, "Spawned enemy");
                     }
+                    AdminCommand::Pause
+                    | AdminCommand::Resume
+                    | AdminCommand::Step
+                    | AdminCommand::SetTickRate(_)
+                    | AdminCommand::Quit(_) => {}
+                }
+            }
+        })
+}
+
+/// Writes a world snapshot to the replay every `snapshot_interval` frames while recording.
+pub fn replay_record_system() -> Box<dyn Schedulable> {
+    SystemBuilder::new("replay_record_system")
+        .write_resource::<ReplayRecorder>()
+        .read_resource::<CommandFrameTicker>()
+        .with_query(<(
+            Read<UidComponent>,
+            TryRead<Position>,
+            TryRead<PlayerType>,
+            TryRead<PlayerInfo>,
+            TryRead<Health>,
+            TryRead<Pickup>,
+            TryRead<Score>,
+        )>::query())
+        .build(|_, mut world, resource, query| {
+            let recorder: &mut ReplayRecorder = &mut resource.0;
+            let command_frame = resource.1.command_frame();
+
+            if !recorder.is_recording() {
+                return;
+            }
+
+            if command_frame % recorder.snapshot_interval == 0 {
+                let mut snapshot = WorldSnapshot::new(command_frame);
+
+                for (uid, pos, player_type, info, health, pickup, score) in query.iter(&mut world) {
+                    snapshot.entities.push(EntitySnapshot::new(
+                        uid.uid(),
+                        pos.as_deref(),
+                        player_type.as_deref(),
+                        info.as_deref(),
+                        health.as_deref(),
+                        pickup.as_deref(),
+                        score.as_deref(),
+                    ));
                 }
+
+                recorder.record(ReplayEvent::Snapshot(snapshot));
             }
+
+            recorder.flush();
+        })
+}
+
+/// Applies the recorded connection events and commands of the current frame, replacing
+/// `handle_messages_system` and `handle_commands_system` when replaying.
+pub fn replay_events_system() -> Box<dyn Schedulable> {
+    SystemBuilder::new("replay_events_system")
+        .write_resource::<ReplayPlayback>()
+        .write_resource::<UidAllocator<Entity>>()
+        .write_resource::<PlayerSettings>()
+        .read_resource::<CommandFrameTicker>()
+        .write_resource::<CommandQueue>()
+        .with_query(<(
+            legion::prelude::Write<Position>,
+            Read<PlayerInfo>,
+            Read<UidComponent>,
+        )>::query())
+        .build(|command, mut world, resource, query| {
+            let playback: &mut ReplayPlayback = &mut resource.0;
+            let uid_allocator: &mut UidAllocator<Entity> = &mut resource.1;
+            let player_settings: &mut PlayerSettings = &mut resource.2;
+            let current_frame = resource.3.command_frame();
+            let command_queue: &mut CommandQueue = &mut resource.4;
+
+            let mut mismatches = Vec::new();
+
+            if let Err(e) = playback.advance(current_frame) {
+                mismatches.push(e);
+            }
+
+            for event in playback.replay.events_at(current_frame) {
+                match event {
+                    ReplayEvent::Connected {
+                        client_id,
+                        uid,
+                        position,
+                        ..
+                    } => {
+                        for (entity, (_, info, _)) in query.iter_entities_mut(&mut world) {
+                            if info.client_id() == *client_id {
+                                command.delete(entity);
+                                uid_allocator.deallocate(entity);
+                            }
+                        }
+
+                        // Keep the spawn point rotation in step with the recording.
+                        player_settings.next_spawn_point();
+
+                        let id = spawn_player(
+                            command,
+                            uid_allocator,
+                            *client_id,
+                            *position,
+                            player_settings.max_health,
+                        );
+
+                        if id != *uid {
+                            mismatches.push(format!(
+                                "Frame {}: player of client {} got uid {}, recorded {}",
+                                current_frame, client_id, id, uid
+                            ));
+                        }
+                    }
+                    ReplayEvent::Disconnected { client_id, .. } => {
+                        for (entity, (_, info, _)) in query.iter_entities_mut(&mut world) {
+                            if info.client_id() == *client_id {
+                                command.delete(entity);
+                                uid_allocator.deallocate(entity);
+                            }
+                        }
+                    }
+                    ReplayEvent::Command {
+                        command_frame,
+                        client_id,
+                        command: client_command,
+                        ..
+                    } => {
+                        command_queue.push(*client_id, *command_frame, client_command.clone());
+                    }
+                    ReplayEvent::Snapshot(_) | ReplayEvent::Resimulated { .. } => {}
+                }
+            }
+
+            playback.mismatches.extend(mismatches);
+        })
+}
+
+/// Compares the replayed `Position` values with the recorded snapshot of the current frame.
+pub fn replay_verify_system() -> Box<dyn Schedulable> {
+    SystemBuilder::new("replay_verify_system")
+        .write_resource::<ReplayPlayback>()
+        .read_resource::<CommandFrameTicker>()
+        .with_query(<(Read<UidComponent>, Read<Position>)>::query())
+        .build(|_, mut world, resource, query| {
+            let playback: &mut ReplayPlayback = &mut resource.0;
+            let current_frame = resource.1.command_frame();
+
+            let positions: HashMap<u32, (u16, u16)> = query
+                .iter(&mut world)
+                .map(|(uid, pos)| (uid.uid(), (pos.x, pos.y)))
+                .collect();
+
+            let mut mismatches = Vec::new();
+            let mut checked = 0;
+
+            for event in playback.replay.events_at(current_frame) {
+                if let ReplayEvent::Snapshot(snapshot) = event {
+                    checked += 1;
+
+                    for entity in snapshot.entities.iter() {
+                        let recorded = match entity.position {
+                            Some(position) => position,
+                            None => continue,
+                        };
+
+                        match positions.get(&entity.uid) {
+                            Some(replayed) if *replayed == recorded => {}
+                            Some(replayed) => mismatches.push(format!(
+                                "Frame {}: entity {} is at {:?}, recorded {:?}",
+                                current_frame, entity.uid, replayed, recorded
+                            )),
+                            None => mismatches.push(format!(
+                                "Frame {}: entity {} does not exist, recorded at {:?}",
+                                current_frame, entity.uid, recorded
+                            )),
+                        }
+                    }
+                }
+            }
+
+            playback.checked_snapshots += checked;
+            playback.mismatches.extend(mismatches);
         })
 }
 
@@ -169,14 +913,21 @@ pub fn render_server() -> Box<dyn Schedulable> {
         .read_resource::<PostOffice<ServerMessage, ClientMessage, ClientCommand>>()
         .write_resource::<WindowResource>()
         .read_resource::<CommandFrameTicker>()
+        .write_resource::<ServerDiagnostics>()
+        .read_resource::<TileMap>()
+        .write_resource::<TickProfiler>()
         .with_query(<(
             Read<UidComponent>,
             legion::prelude::Read<Position>,
             Read<PlayerType>,
         )>::query())
-        .build(|_, mut world, resources, query| {
+        .with_query(<(Read<Position>, Read<Health>)>::query())
+        .with_query(<(Read<Position>, Read<Pickup>)>::query())
+        .build(|_, mut world, resources, (query, health_query, pickup_query)| {
             let postoffice: &PostOffice<ServerMessage, ClientMessage, ClientCommand> = &resources.0;
             let window_resource: &mut WindowResource = &mut resources.1;
+            let diagnostics: &mut ServerDiagnostics = &mut resources.3;
+            let profiler: &mut TickProfiler = &mut resources.5;
 
             let mut assets = find_folder::Search::ParentsThenKids(3, 3)
                 .for_folder("assets")
@@ -184,91 +935,43 @@ pub fn render_server() -> Box<dyn Schedulable> {
             assets.push("FiraSans-Regular.ttf");
 
             let mut canvas = window_resource.window_lock().unwrap();
+            let mut event_pump = window_resource.event_pump().unwrap();
             let tff_context = window_resource.tff().unwrap();
             let texture_creator = canvas.texture_creator();
 
             // Load a font
-            let font = tff_context.load_font(assets, 50).unwrap();
+            let font = tff_context.load_font(assets, DIAGNOSTICS_FONT_SIZE).unwrap();
 
-            canvas.set_draw_color(Color::RGB(0, 0, 0));
-            canvas.clear();
+            let mut clients: Vec<_> = postoffice.clients().collect();
+            clients.sort_by_key(|(client_id, _)| **client_id);
 
-            static LEFT_MARIGIN: i32 = SCREEN_WIDTH as i32 - 300;
+            let page_count = (clients.len() + DIAGNOSTICS_ROWS_PER_PAGE - 1)
+                / DIAGNOSTICS_ROWS_PER_PAGE;
 
-            let mut y_offset = 0;
-            for client in postoffice.clients() {
-                let client_id_text = format!("PORT: {}", client.1.addr().port());
-                let client_offset_text = format!(
-                    "offset: {}",
-                    client.1.command_postbox().command_frame_offset()
-                );
-                let client_highest_text =
-                    format!("highest: {}", client.1.command_postbox().highest_seen());
-
-                let rendered_text =
-                    create_texture_from_text(&texture_creator, &font, &client_id_text, 255, 0, 0);
-                canvas
-                    .copy(
-                        &rendered_text,
-                        None,
-                        Some(get_rect_from_text(&client_id_text, LEFT_MARIGIN, y_offset)),
-                    )
-                    .unwrap();
+            // === Read input
+            for event in event_pump.poll_iter() {
+                match event {
+                    sdl2::event::Event::KeyDown { keycode, .. } => match keycode {
+                        Some(Keycode::PageDown) => diagnostics.next_page(page_count),
+                        Some(Keycode::PageUp) => diagnostics.previous_page(),
+                        Some(Keycode::F2) => profiler.hud_visible = !profiler.hud_visible,
+                        _ => {}
+                    },
+                    _ => {}
+                }
+            }
 
-                y_offset += 50;
+            diagnostics.clamp_page(page_count);
 
-                let rendered_text = create_texture_from_text(
-                    &texture_creator,
-                    &font,
-                    &client_offset_text,
-                    255,
-                    0,
-                    0,
-                );
-                canvas
-                    .copy(
-                        &rendered_text,
-                        None,
-                        Some(get_rect_from_text(&client_id_text, LEFT_MARIGIN, y_offset)),
-                    )
-                    .unwrap();
-
-                y_offset += 50;
+            canvas.set_draw_color(Color::RGB(0, 0, 0));
+            canvas.clear();
 
-                let rendered_text = create_texture_from_text(
-                    &texture_creator,
-                    &font,
-                    &client_highest_text,
-                    255,
-                    0,
-                    0,
-                );
-                canvas
-                    .copy(
-                        &rendered_text,
-                        None,
-                        Some(get_rect_from_text(&client_id_text, LEFT_MARIGIN, y_offset)),
-                    )
-                    .unwrap();
+            draw_map(&mut canvas, &resources.4);
 
-                y_offset += 100;
+            for (pos, pickup) in pickup_query.iter(&mut world) {
+                draw_pickup(&mut canvas, pos.x, pos.y, &pickup);
             }
 
-            let command_frame_text = format!("CF: {}", resources.2.command_frame());
-            let rendered_text =
-                create_texture_from_text(&texture_creator, &font, &command_frame_text, 255, 0, 0);
-            canvas
-                .copy(
-                    &rendered_text,
-                    None,
-                    Some(get_rect_from_text(
-                        &command_frame_text,
-                        LEFT_MARIGIN,
-                        y_offset,
-                    )),
-                )
-                .unwrap();
-
             // === Render Players
             for (_id, pos, player_type) in query.iter(&mut world) {
                 if player_type.player_type() == PlayerTypeOp::Enemy {
@@ -280,6 +983,169 @@ pub fn render_server() -> Box<dyn Schedulable> {
                 }
             }
 
+            for (pos, health) in health_query.iter(&mut world) {
+                draw_health_bar(&mut canvas, pos.x, pos.y, &health);
+            }
+
+            // === Render Profile
+            if profiler.is_enabled() && profiler.hud_visible {
+                let mut y_offset = DIAGNOSTICS_MARGIN;
+
+                render_text(
+                    &mut canvas,
+                    &texture_creator,
+                    &font,
+                    "Stage avg/max ms (F2)",
+                    PROFILE_HUD_X,
+                    y_offset,
+                    Color::RGB(200, 200, 200),
+                );
+                y_offset += DIAGNOSTICS_ROW_HEIGHT;
+
+                for stage in profiler.stages() {
+                    let line = format!(
+                        "{}: {:.2}/{:.2}",
+                        stage.name,
+                        stage.average().as_secs_f64() * 1000.0,
+                        stage.max().as_secs_f64() * 1000.0
+                    );
+
+                    render_text(
+                        &mut canvas,
+                        &texture_creator,
+                        &font,
+                        &line,
+                        PROFILE_HUD_X,
+                        y_offset,
+                        Color::RGB(255, 255, 0),
+                    );
+                    y_offset += DIAGNOSTICS_ROW_HEIGHT;
+                }
+            }
+
+            // === Render Diagnostics
+            canvas.set_draw_color(Color::RGB(60, 60, 60));
+            canvas.draw_line(
+                (0, SCREEN_HEIGHT as i32),
+                (SCREEN_WIDTH as i32, SCREEN_HEIGHT as i32),
+            );
+
+            let text_color = Color::RGB(255, 0, 0);
+            let mut y_offset = SCREEN_HEIGHT as i32 + 10;
+
+            render_text(
+                &mut canvas,
+                &texture_creator,
+                &font,
+                &format!(
+                    "CF: {}   Clients: {}   Page {}/{} (PgUp/PgDn)",
+                    resources.2.command_frame(),
+                    clients.len(),
+                    diagnostics.page() + 1,
+                    page_count.max(1)
+                ),
+                DIAGNOSTICS_MARGIN,
+                y_offset,
+                text_color,
+            );
+            y_offset += DIAGNOSTICS_ROW_HEIGHT;
+
+            render_row(
+                &mut canvas,
+                &texture_creator,
+                &font,
+                &DIAGNOSTICS_COLUMNS
+                    .iter()
+                    .map(|(title, _)| title.to_string())
+                    .collect::<Vec<_>>(),
+                y_offset,
+                Color::RGB(200, 200, 200),
+            );
+            y_offset += DIAGNOSTICS_ROW_HEIGHT;
+
+            for (client_id, client) in clients
+                .iter()
+                .skip(diagnostics.page() * DIAGNOSTICS_ROWS_PER_PAGE)
+                .take(DIAGNOSTICS_ROWS_PER_PAGE)
+            {
+                let command_postbox = client.command_postbox();
+
+                let (name, rtt, late, early, bandwidth) = match diagnostics.client(**client_id) {
+                    Some(client_diagnostics) => (
+                        client_diagnostics.name.clone(),
+                        client_diagnostics
+                            .statistics
+                            .rtt()
+                            .map_or("-".to_string(), |rtt| format!("{} ms", rtt.as_millis())),
+                        client_diagnostics.late_commands.to_string(),
+                        format!(
+                            "{}/{}",
+                            client_diagnostics.early_commands, client_diagnostics.dropped_commands
+                        ),
+                        format!(
+                            "{}/{}",
+                            client_diagnostics.statistics.bytes_in_per_second(),
+                            client_diagnostics.statistics.bytes_out_per_second()
+                        ),
+                    ),
+                    None => (
+                        "-".to_string(),
+                        "-".to_string(),
+                        "-".to_string(),
+                        "-".to_string(),
+                        "-".to_string(),
+                    ),
+                };
+
+                render_row(
+                    &mut canvas,
+                    &texture_creator,
+                    &font,
+                    &[
+                        client_id.to_string(),
+                        client.addr().to_string(),
+                        name,
+                        rtt,
+                        command_postbox.command_frame_offset().to_string(),
+                        command_postbox.highest_seen().to_string(),
+                        late,
+                        early,
+                        bandwidth,
+                    ],
+                    y_offset,
+                    text_color,
+                );
+                y_offset += DIAGNOSTICS_ROW_HEIGHT;
+            }
+
             canvas.present()
         })
 }
+
+fn render_row(
+    canvas: &mut WindowCanvas,
+    texture_creator: &TextureCreator<WindowContext>,
+    font: &Font,
+    cells: &[String],
+    y: i32,
+    color: Color,
+) {
+    let mut x = DIAGNOSTICS_MARGIN;
+
+    for (cell, (_, width)) in cells.iter().zip(DIAGNOSTICS_COLUMNS.iter()) {
+        render_text(canvas, texture_creator, font, cell, x, y, color);
+        x += width;
+    }
+}
+
+pub struct PingTimer {
+    last_ping: Instant,
+}
+
+impl PingTimer {
+    pub fn new() -> PingTimer {
+        PingTimer {
+            last_ping: Instant::now(),
+        }
+    }
+}
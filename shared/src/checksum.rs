@@ -0,0 +1,77 @@
+use std::collections::{HashMap, HashSet};
+
+use serde::{Deserialize, Serialize};
+
+use crate::snapshot::EntitySnapshot;
+
+const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+/// 64 bit FNV-1a hash of the bytes.
+///
+/// The std hashers don't specify their algorithm, this one is fixed, so server and client
+/// binaries built with different toolchains agree on it.
+pub fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(FNV_OFFSET_BASIS, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(FNV_PRIME)
+    })
+}
+
+/// FNV-1a hash of the bincode encoding of the value,
+/// which writes integers with a fixed size in little endian.
+pub fn checksum_of<T: Serialize>(value: &T) -> u64 {
+    fnv1a(&bincode::serialize(value).expect("Checksummed values always serialize"))
+}
+
+/// Checksums of the synchronized components of all entities at a command frame.
+#[derive(Debug, Clone, Serialize, Deserialize, Hash, Eq, PartialEq)]
+pub struct WorldChecksum {
+    pub command_frame: u32,
+    /// Combined checksum of all entities, equal checksums mean nothing diverged.
+    pub checksum: u64,
+    /// Uid and checksum of every entity, sorted by uid.
+    pub entities: Vec<(u32, u64)>,
+}
+
+impl WorldChecksum {
+    pub fn new(command_frame: u32, entities: &[EntitySnapshot]) -> WorldChecksum {
+        let mut entities: Vec<(u32, u64)> = entities
+            .iter()
+            .map(|entity| (entity.uid, entity.checksum()))
+            .collect();
+        entities.sort();
+
+        WorldChecksum {
+            command_frame,
+            checksum: checksum_of(&entities),
+            entities,
+        }
+    }
+
+    /// Returns the uids of entities that differ from `other`, including entities only one side knows about.
+    pub fn divergent(&self, other: &WorldChecksum) -> Vec<u32> {
+        if self.checksum == other.checksum {
+            return Vec::new();
+        }
+
+        let theirs: HashMap<u32, u64> = other.entities.iter().cloned().collect();
+        let ours: HashSet<u32> = self.entities.iter().map(|(uid, _)| *uid).collect();
+
+        let mut uids: Vec<u32> = self
+            .entities
+            .iter()
+            .filter(|(uid, checksum)| theirs.get(uid) != Some(checksum))
+            .map(|(uid, _)| *uid)
+            .chain(
+                other
+                    .entities
+                    .iter()
+                    .map(|(uid, _)| *uid)
+                    .filter(|uid| !ours.contains(uid)),
+            )
+            .collect();
+        uids.sort();
+
+        uids
+    }
+}
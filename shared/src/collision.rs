@@ -0,0 +1,50 @@
+use crate::components::Position;
+use serde::{Deserialize, Serialize};
+
+/// Width and height of every player and enemy.
+pub const ENTITY_SIZE: u16 = 50;
+/// Width and height of every pickup.
+pub const PICKUP_SIZE: u16 = 20;
+
+/// An axis aligned bounding box.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Hash, Eq, PartialEq)]
+pub struct Aabb {
+    pub x: u16,
+    pub y: u16,
+    pub width: u16,
+    pub height: u16,
+}
+
+impl Aabb {
+    pub fn new(x: u16, y: u16, width: u16, height: u16) -> Aabb {
+        Aabb {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    /// Returns the box of an entity standing at the given position.
+    pub fn from_position(x: u16, y: u16) -> Aabb {
+        Aabb::new(x, y, ENTITY_SIZE, ENTITY_SIZE)
+    }
+
+    /// Returns the box of a pickup lying at the given position.
+    pub fn from_pickup_position(x: u16, y: u16) -> Aabb {
+        Aabb::new(x, y, PICKUP_SIZE, PICKUP_SIZE)
+    }
+
+    pub fn intersects(&self, other: &Aabb) -> bool {
+        (self.x as u32) < other.x as u32 + other.width as u32
+            && (other.x as u32) < self.x as u32 + self.width as u32
+            && (self.y as u32) < other.y as u32 + other.height as u32
+            && (other.y as u32) < self.y as u32 + self.height as u32
+    }
+}
+
+impl From<&Position> for Aabb {
+    fn from(position: &Position) -> Self {
+        Aabb::from_position(position.x, position.y)
+    }
+}
@@ -0,0 +1,283 @@
+use std::ops::DerefMut;
+
+use legion::{prelude::*, systems::SubWorld};
+use legion_sync::components::UidComponent;
+use net_sync::{
+    synchronisation::{ClientCommandBuffer, ModifiedComponentsBuffer},
+    tracker::Trackable,
+    transport::ClientId,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    collision::Aabb,
+    components::{PlayerInfo, Position},
+    map::TileMap,
+    MOVE_VELOCITY,
+};
+
+/// A command a client sends to control its player.
+///
+/// The same `apply` is used by the client prediction, the client resimulation and the server,
+/// so it has to be deterministic: the result may only depend on the command, the world and the map.
+///
+/// Systems calling `apply` have to declare `write_component` for every component in
+/// `tracked_components!`, `read_component::<PlayerInfo>()` and `read_component::<UidComponent>()`,
+/// the components commands and their tracking use.
+pub trait Command {
+    /// Applies the command to the player entity of the client that sent it.
+    fn apply(&self, world: &mut SubWorld, entity: Entity, map: &TileMap);
+}
+
+/// Declares `ClientCommand`, the command sent over the network, with a variant for every command type.
+macro_rules! client_commands {
+    ($($command:ident),* $(,)?) => {
+        #[derive(Debug, Clone, Serialize, Deserialize, Hash, Eq, PartialEq)]
+        pub enum ClientCommand {
+            $($command($command)),*
+        }
+
+        impl Command for ClientCommand {
+            fn apply(&self, world: &mut SubWorld, entity: Entity, map: &TileMap) {
+                match self {
+                    $(ClientCommand::$command(command) => command.apply(world, entity, map)),*
+                }
+            }
+        }
+    };
+}
+
+// Adding a command takes a type implementing `Command` and its name in this list,
+// the client maps keys to it in `client/src/input.rs`.
+client_commands!(Move);
+
+/// Moves the player one step, the player stays in place when the step runs into another player or a wall.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Hash, Eq, PartialEq)]
+pub enum Move {
+    Up,
+    Right,
+    Down,
+    Left,
+}
+
+impl Move {
+    /// Where a player standing at the given position ends up.
+    pub fn destination(&self, x: u16, y: u16, obstacles: &[Aabb]) -> (u16, u16) {
+        let new_pos = match self {
+            Move::Up => (x, y.saturating_sub(MOVE_VELOCITY)),
+            Move::Right => (x.saturating_add(MOVE_VELOCITY), y),
+            Move::Down => (x, y.saturating_add(MOVE_VELOCITY)),
+            Move::Left => (x.saturating_sub(MOVE_VELOCITY), y),
+        };
+
+        let player_box = Aabb::from_position(new_pos.0, new_pos.1);
+
+        if obstacles.iter().any(|obstacle| obstacle.intersects(&player_box)) {
+            (x, y)
+        } else {
+            new_pos
+        }
+    }
+}
+
+impl Command for Move {
+    fn apply(&self, world: &mut SubWorld, entity: Entity, map: &TileMap) {
+        let obstacles: Vec<Aabb> = <(Read<Position>, Read<PlayerInfo>)>::query()
+            .iter_entities(world)
+            .filter(|(other, _)| *other != entity)
+            .map(|(_, (pos, _))| Aabb::from(&*pos))
+            .chain(map.walls().iter().cloned())
+            .collect();
+
+        if let Some(mut pos) = world.get_component_mut::<Position>(entity) {
+            let destination = self.destination(pos.x, pos.y, &obstacles);
+            pos.set(destination);
+        }
+    }
+}
+
+/// Commands waiting to be applied by `apply_commands_system` this tick.
+///
+/// The network and input systems push the commands, the shared simulation applies them,
+/// so the server and the clients run the same code for every command.
+pub struct CommandQueue {
+    commands: Vec<(ClientId, u32, ClientCommand)>,
+}
+
+impl CommandQueue {
+    pub fn new() -> CommandQueue {
+        CommandQueue {
+            commands: Vec::new(),
+        }
+    }
+
+    /// Queues a command of the given client, tracked at the given command frame.
+    pub fn push(&mut self, client_id: ClientId, command_frame: u32, command: ClientCommand) {
+        self.commands.push((client_id, command_frame, command));
+    }
+
+    pub fn drain(&mut self) -> Vec<(ClientId, u32, ClientCommand)> {
+        self.commands.drain(..).collect()
+    }
+}
+
+/// Where the changes of an applied command are recorded,
+/// the modified components buffer on the server and the command buffer on the client.
+pub trait CommandTracker {
+    fn apply_tracked(
+        &mut self,
+        command: ClientCommand,
+        world: &mut SubWorld,
+        entity: Entity,
+        map: &TileMap,
+        command_frame: u32,
+    );
+}
+
+impl CommandTracker for ModifiedComponentsBuffer {
+    fn apply_tracked(
+        &mut self,
+        command: ClientCommand,
+        world: &mut SubWorld,
+        entity: Entity,
+        map: &TileMap,
+        command_frame: u32,
+    ) {
+        apply_on_server(&command, world, entity, map, self, command_frame);
+    }
+}
+
+impl CommandTracker for ClientCommandBuffer<ClientCommand> {
+    fn apply_tracked(
+        &mut self,
+        command: ClientCommand,
+        world: &mut SubWorld,
+        entity: Entity,
+        map: &TileMap,
+        command_frame: u32,
+    ) {
+        predict_on_client(command, world, entity, map, self, command_frame);
+    }
+}
+
+/// Declares the components commands may change.
+///
+/// Their state is taken before a command is applied, afterwards every one of them the player has
+/// is restored and the change is redone on a tracked borrow, so tracking records it. Adding a
+/// component here is all a command needs to change it, the systems calling `apply` have to
+/// declare `write_component` for it.
+macro_rules! tracked_components {
+    ($($component:ident),* $(,)?) => {
+        /// The tracked components of an entity before a command was applied.
+        #[allow(non_snake_case)]
+        struct TrackedComponents {
+            $($component: Option<$component>),*
+        }
+
+        impl TrackedComponents {
+            fn take(world: &SubWorld, entity: Entity) -> TrackedComponents {
+                TrackedComponents {
+                    $($component: world
+                        .get_component::<$component>(entity)
+                        .map(|component| (*component).clone())),*
+                }
+            }
+
+            fn track_on_server(
+                self,
+                world: &mut SubWorld,
+                entity: Entity,
+                modified_components_buffer: &mut ModifiedComponentsBuffer,
+                uid: u32,
+                command_frame: u32,
+            ) {
+                $(
+                    if let (Some(before), Some(mut component)) =
+                        (self.$component, world.get_component_mut::<$component>(entity))
+                    {
+                        let after = std::mem::replace(&mut *component, before);
+
+                        let mut component =
+                            component.server_track(modified_components_buffer, uid, command_frame);
+                        *component.deref_mut() = after;
+                    }
+                )*
+            }
+
+            fn track_on_client(
+                self,
+                world: &mut SubWorld,
+                entity: Entity,
+                command: &ClientCommand,
+                client_command_buffer: &mut ClientCommandBuffer<ClientCommand>,
+                uid: u32,
+                command_frame: u32,
+            ) {
+                $(
+                    if let (Some(before), Some(mut component)) =
+                        (self.$component, world.get_component_mut::<$component>(entity))
+                    {
+                        let after = std::mem::replace(&mut *component, before);
+
+                        let mut component = component.client_track(
+                            client_command_buffer,
+                            command.clone(),
+                            uid,
+                            command_frame,
+                        );
+                        *component.deref_mut() = after;
+                    }
+                )*
+            }
+        }
+    };
+}
+
+tracked_components!(Position);
+
+/// Applies a command on the server and records the changes for synchronisation.
+pub fn apply_on_server(
+    command: &ClientCommand,
+    world: &mut SubWorld,
+    entity: Entity,
+    map: &TileMap,
+    modified_components_buffer: &mut ModifiedComponentsBuffer,
+    command_frame: u32,
+) {
+    let uid = match world.get_component::<UidComponent>(entity) {
+        Some(uid) => **uid,
+        None => return,
+    };
+    let before = TrackedComponents::take(world, entity);
+
+    command.apply(world, entity, map);
+
+    before.track_on_server(world, entity, modified_components_buffer, uid, command_frame);
+}
+
+/// Predicts a command on the client and records it with its changes, which sends it to the server.
+pub fn predict_on_client(
+    command: ClientCommand,
+    world: &mut SubWorld,
+    entity: Entity,
+    map: &TileMap,
+    client_command_buffer: &mut ClientCommandBuffer<ClientCommand>,
+    command_frame: u32,
+) {
+    let uid = match world.get_component::<UidComponent>(entity) {
+        Some(uid) => **uid,
+        None => return,
+    };
+    let before = TrackedComponents::take(world, entity);
+
+    command.apply(world, entity, map);
+
+    before.track_on_client(
+        world,
+        entity,
+        &command,
+        client_command_buffer,
+        uid,
+        command_frame,
+    );
+}
@@ -0,0 +1,178 @@
+use net_sync::transport::ClientId;
+use tracing::debug;
+
+use crate::message::{ClientCommand, CommandRejection};
+
+/// What to do with a command that arrives after its command frame has been simulated.
+///
+/// Rolling back is not supported, the server keeps no history of the world to rewind to.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LateCommandPolicy {
+    /// Apply the command at the current command frame.
+    ApplyAtCurrentFrame,
+    /// Drop the command and tell the client it was rejected.
+    DropAndNotify,
+}
+
+impl LateCommandPolicy {
+    pub fn parse(policy: &str) -> Result<LateCommandPolicy, String> {
+        match policy {
+            "apply" => Ok(LateCommandPolicy::ApplyAtCurrentFrame),
+            "drop" => Ok(LateCommandPolicy::DropAndNotify),
+            _ => Err(format!(
+                "Unknown late command policy '{}', expected apply or drop",
+                policy
+            )),
+        }
+    }
+}
+
+/// What to do with a command that is further ahead than the server is willing to buffer.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EarlyCommandPolicy {
+    /// Apply the command at the current command frame.
+    ApplyAtCurrentFrame,
+    /// Drop the command and tell the client it was rejected.
+    DropAndNotify,
+}
+
+impl EarlyCommandPolicy {
+    pub fn parse(policy: &str) -> Result<EarlyCommandPolicy, String> {
+        match policy {
+            "apply" => Ok(EarlyCommandPolicy::ApplyAtCurrentFrame),
+            "drop" => Ok(EarlyCommandPolicy::DropAndNotify),
+            _ => Err(format!(
+                "Unknown early command policy '{}', expected apply or drop",
+                policy
+            )),
+        }
+    }
+}
+
+pub struct CommandPolicy {
+    pub late: LateCommandPolicy,
+    pub early: EarlyCommandPolicy,
+    /// How many frames back late commands are still looked up.
+    pub max_frames_late: u32,
+    /// How many frames before `max_frames_late` are still drained, commands found there are
+    /// dropped and the client is notified. Older commands stay in the postbox,
+    /// the server can only find commands by scanning frames.
+    pub stale_frames: u32,
+    /// How many frames ahead of the current command frame commands are buffered.
+    pub max_frames_ahead: u32,
+}
+
+/// The commands of one client that were taken from its postbox this frame.
+#[derive(Default)]
+pub struct DueCommands {
+    /// The frame each command was sent for and the command, all are applied at the current frame.
+    pub apply: Vec<(u32, ClientCommand)>,
+    /// The frames of commands that were dropped and why.
+    pub rejected: Vec<(u32, CommandRejection)>,
+    pub late: u32,
+    pub early: u32,
+    pub dropped: u32,
+}
+
+impl CommandPolicy {
+    pub fn new(late: LateCommandPolicy, early: EarlyCommandPolicy) -> CommandPolicy {
+        CommandPolicy {
+            late,
+            early,
+            max_frames_late: 30,
+            stale_frames: 90,
+            max_frames_ahead: 60,
+        }
+    }
+
+    /// Takes the commands of a client that are due at `current_frame` and sorts them by this policy.
+    ///
+    /// `drain_frame` takes the commands the client sent for a frame from its postbox,
+    /// `highest_seen` is the highest frame the client sent a command for.
+    pub fn take_due_commands(
+        &self,
+        client_id: ClientId,
+        current_frame: u32,
+        highest_seen: u32,
+        mut drain_frame: impl FnMut(u32) -> Vec<ClientCommand>,
+    ) -> DueCommands {
+        let mut due = DueCommands::default();
+
+        for command in drain_frame(current_frame) {
+            due.apply.push((current_frame, command));
+        }
+
+        // Commands too late to be applied at all, still drained so they don't pile up.
+        let oldest_late_frame = current_frame.saturating_sub(self.max_frames_late);
+        let oldest_stale_frame = oldest_late_frame.saturating_sub(self.stale_frames);
+        for frame in oldest_stale_frame..oldest_late_frame {
+            for _ in drain_frame(frame) {
+                debug!(
+                    client_id = %client_id,
+                    command_frame = frame,
+                    current_frame,
+                    "Stale command"
+                );
+                due.late += 1;
+                due.dropped += 1;
+                due.rejected.push((frame, CommandRejection::Late));
+            }
+        }
+
+        // Commands for frames that were already simulated.
+        for frame in oldest_late_frame..current_frame {
+            for command in drain_frame(frame) {
+                debug!(
+                    client_id = %client_id,
+                    command_frame = frame,
+                    current_frame,
+                    "Late command"
+                );
+                due.late += 1;
+
+                match self.late {
+                    LateCommandPolicy::ApplyAtCurrentFrame => due.apply.push((frame, command)),
+                    LateCommandPolicy::DropAndNotify => {
+                        due.dropped += 1;
+                        due.rejected.push((frame, CommandRejection::Late));
+                    }
+                }
+            }
+        }
+
+        // Commands too far ahead of the server. The highest seen frame comes from the client,
+        // so only one more buffer length is scanned, commands beyond stay in the postbox.
+        let max_frame = current_frame.saturating_add(self.max_frames_ahead);
+        let last_frame = highest_seen.min(max_frame.saturating_add(self.max_frames_ahead));
+        for frame in max_frame.saturating_add(1)..=last_frame {
+            for command in drain_frame(frame) {
+                debug!(
+                    client_id = %client_id,
+                    command_frame = frame,
+                    current_frame,
+                    "Early command"
+                );
+                due.early += 1;
+
+                match self.early {
+                    EarlyCommandPolicy::ApplyAtCurrentFrame => due.apply.push((frame, command)),
+                    EarlyCommandPolicy::DropAndNotify => {
+                        due.dropped += 1;
+                        due.rejected.push((frame, CommandRejection::TooEarly));
+                    }
+                }
+            }
+        }
+
+        due
+    }
+}
+
+impl Default for CommandPolicy {
+    fn default() -> Self {
+        CommandPolicy::new(
+            LateCommandPolicy::ApplyAtCurrentFrame,
+            EarlyCommandPolicy::DropAndNotify,
+        )
+    }
+}
@@ -1,3 +1,5 @@
+use std::convert::TryFrom;
+
 use legion_sync::tracking::*;
 use net_sync::transport::ClientId;
 
@@ -96,3 +98,106 @@ impl Default for PlayerInfo {
 }
 
 
+
+#[sync]
+#[derive(Debug, PartialOrd, PartialEq)]
+pub struct Health {
+    pub current: u16,
+    pub max: u16,
+}
+
+impl Health {
+    pub fn new(max: u16) -> Health {
+        Health { current: max, max }
+    }
+
+    pub fn damage(&mut self, amount: u16) {
+        self.current = self.current.saturating_sub(amount);
+    }
+
+    pub fn restore(&mut self) {
+        self.current = self.max;
+    }
+
+    pub fn is_dead(&self) -> bool {
+        self.current == 0
+    }
+}
+
+impl Default for Health {
+    fn default() -> Self {
+        Health::new(100)
+    }
+}
+
+#[sync]
+#[derive(Debug, PartialOrd, PartialEq)]
+pub struct Pickup {
+    pub kind: u16,
+    pub value: u16,
+}
+
+impl Pickup {
+    pub fn new(kind: PickupKind) -> Pickup {
+        Pickup {
+            value: kind.value(),
+            kind: kind as u16,
+        }
+    }
+
+    /// The kind comes from the network or a replay file, unknown kinds are `None`.
+    pub fn kind(&self) -> Option<PickupKind> {
+        PickupKind::try_from(self.kind).ok()
+    }
+}
+
+impl Default for Pickup {
+    fn default() -> Self {
+        Pickup::new(PickupKind::Coin)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialOrd, PartialEq)]
+pub enum PickupKind {
+    Coin = 1,
+    Gem = 2,
+}
+
+impl TryFrom<u16> for PickupKind {
+    type Error = String;
+
+    fn try_from(kind: u16) -> Result<Self, Self::Error> {
+        match kind {
+            1 => Ok(PickupKind::Coin),
+            2 => Ok(PickupKind::Gem),
+            _ => Err(format!("Unknown pickup kind {}", kind)),
+        }
+    }
+}
+
+impl PickupKind {
+    pub fn value(&self) -> u16 {
+        match self {
+            PickupKind::Coin => 1,
+            PickupKind::Gem => 5,
+        }
+    }
+}
+
+#[sync]
+#[derive(Debug, PartialOrd, PartialEq)]
+pub struct Score {
+    pub points: u32,
+}
+
+impl Score {
+    pub fn add(&mut self, points: u16) {
+        self.points += points as u32;
+    }
+}
+
+impl Default for Score {
+    fn default() -> Self {
+        Score { points: 0 }
+    }
+}
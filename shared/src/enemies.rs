@@ -0,0 +1,183 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    collision::{Aabb, ENTITY_SIZE},
+    map::TileMap,
+    SCREEN_HEIGHT, SCREEN_WIDTH,
+};
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Hash, Eq, PartialEq)]
+pub enum MovementPattern {
+    /// Walk back and forth horizontally over the given distance from the spawn point.
+    Patrol { distance: u16 },
+    /// Walk towards the nearest player.
+    ChaseNearestPlayer,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Hash, Eq, PartialEq)]
+pub struct SpawnPoint {
+    pub x: u16,
+    pub y: u16,
+    pub pattern: MovementPattern,
+}
+
+impl SpawnPoint {
+    pub fn new(x: u16, y: u16, pattern: MovementPattern) -> SpawnPoint {
+        SpawnPoint { x, y, pattern }
+    }
+}
+
+pub struct EnemySettings {
+    pub spawn_points: Vec<SpawnPoint>,
+    /// Spawn an enemy every this many command frames, 0 disables spawning.
+    pub spawn_interval: u32,
+    pub max_count: usize,
+    /// Move enemies every this many command frames, 0 keeps them in place.
+    pub move_interval: u32,
+    pub velocity: u16,
+    /// Despawn enemies after this many command frames, if set.
+    pub lifetime: Option<u32>,
+    next_spawn_point: usize,
+}
+
+impl EnemySettings {
+    pub fn new(spawn_points: Vec<SpawnPoint>) -> EnemySettings {
+        EnemySettings {
+            spawn_points,
+            spawn_interval: 100,
+            max_count: 5,
+            move_interval: 5,
+            velocity: 10,
+            lifetime: Some(1000),
+            next_spawn_point: 0,
+        }
+    }
+
+    /// Returns the spawn points round robin.
+    pub fn next_spawn_point(&mut self) -> Option<SpawnPoint> {
+        if self.spawn_points.is_empty() {
+            return None;
+        }
+
+        let spawn_point = self.spawn_points[self.next_spawn_point % self.spawn_points.len()];
+        self.next_spawn_point = (self.next_spawn_point + 1) % self.spawn_points.len();
+        Some(spawn_point)
+    }
+
+    /// Uses the enemy spawn points of the map, or the default ones if the map has none.
+    pub fn for_map(map: &TileMap) -> EnemySettings {
+        if map.enemy_spawns().is_empty() {
+            EnemySettings::default()
+        } else {
+            EnemySettings::new(map.enemy_spawns().to_vec())
+        }
+    }
+}
+
+impl Default for EnemySettings {
+    fn default() -> Self {
+        EnemySettings::new(vec![
+            SpawnPoint::new(20, 20, MovementPattern::Patrol { distance: 200 }),
+            SpawnPoint::new(
+                (SCREEN_WIDTH as u16) - ENTITY_SIZE - 20,
+                (SCREEN_HEIGHT as u16) - ENTITY_SIZE - 20,
+                MovementPattern::ChaseNearestPlayer,
+            ),
+        ])
+    }
+}
+
+/// Server only state of an enemy, this is not synchronized.
+#[derive(Debug)]
+pub struct Enemy {
+    pub pattern: MovementPattern,
+    pub spawned_at: u32,
+    pub origin: (u16, u16),
+    pub moving_forward: bool,
+}
+
+impl Enemy {
+    pub fn new(spawn_point: &SpawnPoint, spawned_at: u32) -> Enemy {
+        Enemy {
+            pattern: spawn_point.pattern,
+            spawned_at,
+            origin: (spawn_point.x, spawn_point.y),
+            moving_forward: true,
+        }
+    }
+
+    /// Calculates the next position of this enemy given the positions of all players.
+    ///
+    /// Enemies stop in front of walls, patrolling enemies turn around.
+    pub fn next_position(
+        &mut self,
+        position: (u16, u16),
+        players: &[(u16, u16)],
+        map: &TileMap,
+        velocity: u16,
+    ) -> (u16, u16) {
+        let (x, y) = position;
+
+        let (x, y) = match self.pattern {
+            MovementPattern::Patrol { distance } => {
+                let end = self.origin.0.saturating_add(distance);
+
+                if self.moving_forward && x >= end {
+                    self.moving_forward = false;
+                } else if !self.moving_forward && x <= self.origin.0 {
+                    self.moving_forward = true;
+                }
+
+                if self.moving_forward {
+                    (x.saturating_add(velocity).min(end), y)
+                } else {
+                    (x.saturating_sub(velocity).max(self.origin.0), y)
+                }
+            }
+            MovementPattern::ChaseNearestPlayer => {
+                let nearest = players.iter().min_by_key(|(player_x, player_y)| {
+                    distance(x, *player_x) as u32 + distance(y, *player_y) as u32
+                });
+
+                match nearest {
+                    Some(&(player_x, player_y)) => {
+                        if distance(x, player_x) >= distance(y, player_y) {
+                            (step_towards(x, player_x, velocity), y)
+                        } else {
+                            (x, step_towards(y, player_y, velocity))
+                        }
+                    }
+                    None => (x, y),
+                }
+            }
+        };
+
+        let (x, y) = (
+            x.min(SCREEN_WIDTH as u16 - ENTITY_SIZE),
+            y.min(SCREEN_HEIGHT as u16 - ENTITY_SIZE),
+        );
+
+        if map.is_blocked(&Aabb::from_position(x, y)) {
+            self.moving_forward = !self.moving_forward;
+            position
+        } else {
+            (x, y)
+        }
+    }
+}
+
+fn distance(a: u16, b: u16) -> u16 {
+    if a > b {
+        a - b
+    } else {
+        b - a
+    }
+}
+
+fn step_towards(from: u16, to: u16, velocity: u16) -> u16 {
+    if from < to {
+        from.saturating_add(velocity).min(to)
+    } else {
+        from.saturating_sub(velocity).max(to)
+    }
+}
@@ -1,36 +1,46 @@
-use crate::message::ClientCommand;
+use crate::components::{Health, Pickup, PickupKind, PlayerType, PlayerTypeOp};
+use crate::map::TileMap;
 use net_sync::transport::ClientId;
 use sdl2::rect::Rect;
-use sdl2::render::{TextureCreator, Texture};
+use sdl2::render::{TextureCreator, Texture, WindowCanvas};
 use sdl2::video::WindowContext;
 use sdl2::ttf::Font;
 use sdl2::pixels::Color;
 
+pub mod checksum;
+pub mod collision;
+pub mod command;
+pub mod command_policy;
 pub mod components;
+pub mod enemies;
+pub mod logging;
+pub mod map;
 pub mod systems;
 pub mod message;
+pub mod pickups;
+pub mod players;
+pub mod replay;
+pub mod session;
+pub mod snapshot;
+pub mod statistics;
 
 pub static LATENCY: u32 = 200;
 pub static COMMAND_FRAME_INTERVAL: u32 = 1000;
 pub static FRAME_INTERVAL: u32 = 200;
 pub static MOVE_VELOCITY: u16 = 20;
 
+pub const MAX_CHAT_MESSAGE_LENGTH: usize = 120;
+pub const MAX_PLAYER_NAME_LENGTH: usize = 20;
+
 pub const SCREEN_WIDTH: u32 = 800;
 pub const SCREEN_HEIGHT: u32 = 500;
 
-pub fn calculate_player_movement(event: &ClientCommand, x: u16, y: u16) -> (u16, u16) {
-    match event {
-        &ClientCommand::MoveUp => (x, y - (1 * MOVE_VELOCITY)),
-        &ClientCommand::MoveRight => (x + (1 * MOVE_VELOCITY), y),
-        &ClientCommand::MoveDown => (x, y + (1 * MOVE_VELOCITY)),
-        &ClientCommand::MoveLeft => (x - (1 * MOVE_VELOCITY), y),
-    }
-}
-
 #[derive(PartialOrd, PartialEq)]
 pub enum ConnectionState {
     Connected,
     Connecting,
+    /// Accepted by the server, waiting for the world snapshot.
+    Synchronizing,
     Disconnected,
     Disconnecting
 }
@@ -38,6 +48,9 @@ pub enum ConnectionState {
 pub struct ConnectionInformation {
     state: ConnectionState,
     client_id: Option<ClientId>,
+    disconnect_reason: Option<String>,
+    /// The command frame of the world snapshot the session started from.
+    snapshot_frame: Option<u32>,
 }
 
 impl ConnectionInformation {
@@ -45,16 +58,41 @@ impl ConnectionInformation {
         ConnectionInformation {
             state: ConnectionState::Disconnected,
             client_id: None,
+            disconnect_reason: None,
+            snapshot_frame: None,
         }
     }
 
-    pub fn set_connected(&mut self, client_id: ClientId) {
+    /// Called with the frame of the world snapshot that completed the handshake.
+    pub fn set_connected(&mut self, snapshot_frame: u32) {
         self.state = ConnectionState::Connected;
+        self.snapshot_frame = Some(snapshot_frame);
+    }
+
+    pub fn set_synchronizing(&mut self, client_id: ClientId) {
+        self.state = ConnectionState::Synchronizing;
         self.client_id = Some(client_id);
+        self.disconnect_reason = None;
+        self.snapshot_frame = None;
     }
 
     pub fn set_disconnected(&mut self) {
         self.state = ConnectionState::Disconnected;
+        self.client_id = None;
+        self.snapshot_frame = None;
+    }
+
+    pub fn snapshot_frame(&self) -> Option<u32> {
+        self.snapshot_frame
+    }
+
+    pub fn set_disconnecting_with_reason(&mut self, reason: String) {
+        self.state = ConnectionState::Disconnecting;
+        self.disconnect_reason = Some(reason);
+    }
+
+    pub fn disconnect_reason(&self) -> Option<&str> {
+        self.disconnect_reason.as_deref()
     }
 
     pub fn set_disconnecting(&mut self) {
@@ -87,6 +125,105 @@ pub fn create_texture_from_text<'a>(texture_creator: &'a TextureCreator<WindowCo
     }
 }
 
+/// Renders text at its natural size for the loaded font and returns the width it took.
+pub fn render_text(canvas: &mut WindowCanvas, texture_creator: &TextureCreator<WindowContext>, font: &Font, text: &str, x: i32, y: i32, color: Color) -> u32 {
+    let texture = create_texture_from_text(texture_creator, font, text, color.r, color.g, color.b);
+    let query = texture.query();
+
+    canvas.copy(&texture, None, Some(Rect::new(x, y, query.width, query.height))).unwrap();
+
+    query.width
+}
+
+pub fn draw_map(canvas: &mut WindowCanvas, map: &TileMap) {
+    canvas.set_draw_color(Color::RGB(100, 100, 100));
+
+    for wall in map.walls() {
+        let _ = canvas.fill_rect(Rect::new(wall.x as i32, wall.y as i32, wall.width as u32, wall.height as u32));
+    }
+}
+
+/// Pickups of a kind this build doesn't know are not drawn.
+pub fn draw_pickup(canvas: &mut WindowCanvas, x: u16, y: u16, pickup: &Pickup) {
+    match pickup.kind() {
+        Some(PickupKind::Coin) => canvas.set_draw_color(Color::RGB(255, 215, 0)),
+        Some(PickupKind::Gem) => canvas.set_draw_color(Color::RGB(0, 200, 255)),
+        None => return,
+    }
+
+    let size = collision::PICKUP_SIZE as u32;
+    let _ = canvas.fill_rect(Rect::new(x as i32, y as i32, size, size));
+}
+
+pub fn draw_entity(canvas: &mut WindowCanvas, x: u16, y: u16, player_type: &PlayerType) {
+    if player_type.player_type() == PlayerTypeOp::Enemy {
+        canvas.set_draw_color(Color::RGB(255, 0, 0));
+    } else {
+        canvas.set_draw_color(Color::RGB(0, 255, 0));
+    }
+
+    let size = collision::ENTITY_SIZE as u32;
+    let _ = canvas.fill_rect(Rect::new(x as i32, y as i32, size, size));
+}
+
+/// Draws a health bar above an entity standing at the given position.
+pub fn draw_health_bar(canvas: &mut WindowCanvas, x: u16, y: u16, health: &Health) {
+    let width = collision::ENTITY_SIZE as u32;
+    let filled = width * health.current as u32 / health.max.max(1) as u32;
+
+    canvas.set_draw_color(Color::RGB(80, 0, 0));
+    let _ = canvas.fill_rect(Rect::new(x as i32, y as i32 - 8, width, 5));
+
+    canvas.set_draw_color(Color::RGB(0, 200, 0));
+    if filled > 0 {
+        let _ = canvas.fill_rect(Rect::new(x as i32, y as i32 - 8, filled, 5));
+    }
+}
+
 pub fn get_rect_from_text(text: &str, x: i32, y: i32) -> Rect {
     Rect::new(x, y, text.len() as u32 * 20, 30)
+}
+
+/// Renders lines of text below each other, starting at the given position.
+pub struct TextColumn<'a> {
+    canvas: &'a mut WindowCanvas,
+    texture_creator: &'a TextureCreator<WindowContext>,
+    font: &'a Font<'a, 'static>,
+    x: i32,
+    y: i32,
+    line_height: i32,
+    color: Color,
+}
+
+impl<'a> TextColumn<'a> {
+    pub fn new(canvas: &'a mut WindowCanvas, texture_creator: &'a TextureCreator<WindowContext>, font: &'a Font<'a, 'static>, x: i32, y: i32) -> TextColumn<'a> {
+        TextColumn {
+            canvas,
+            texture_creator,
+            font,
+            x,
+            y,
+            line_height: 30,
+            color: Color::RGB(255, 0, 0),
+        }
+    }
+
+    pub fn with_color(mut self, r: u8, g: u8, b: u8) -> Self {
+        self.color = Color::RGB(r, g, b);
+        self
+    }
+
+    pub fn line(&mut self, text: &str) {
+        let texture = create_texture_from_text(self.texture_creator, self.font, text, self.color.r, self.color.g, self.color.b);
+        self.canvas.copy(&texture, None, Some(get_rect_from_text(text, self.x, self.y))).unwrap();
+        self.y += self.line_height;
+    }
+
+    pub fn skip(&mut self, lines: i32) {
+        self.y += self.line_height * lines;
+    }
+
+    pub fn y(&self) -> i32 {
+        self.y
+    }
 }
\ No newline at end of file
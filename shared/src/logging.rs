@@ -0,0 +1,60 @@
+use std::{
+    env,
+    fs::File,
+    io::{self, Write},
+    sync::{Arc, Mutex},
+};
+
+use tracing_subscriber::{fmt, prelude::*, EnvFilter};
+
+/// Per module log levels in the `RUST_LOG` syntax, for example `info,server::systems=debug`.
+pub static LOG_FILTER_VARIABLE: &str = "RUST_LOG";
+/// When set, every log event is also written as one JSON object per line to this file.
+pub static LOG_FILE_VARIABLE: &str = "SYNC_LOG_FILE";
+
+static DEFAULT_LOG_FILTER: &str = "info";
+
+/// Logs to the terminal and, when `SYNC_LOG_FILE` is set, to a JSON file.
+///
+/// Events carry their fields, like `client_id`, `command_frame` and `uid`, as structured data,
+/// so the JSON logs of a server and its clients can be joined on them.
+/// Records of the `log` crate, used by the networking crates, are forwarded as well.
+pub fn initialize_logging() -> Result<(), String> {
+    let filter = EnvFilter::try_from_env(LOG_FILTER_VARIABLE)
+        .unwrap_or_else(|_| EnvFilter::new(DEFAULT_LOG_FILTER));
+
+    let json = match env::var_os(LOG_FILE_VARIABLE) {
+        Some(path) => {
+            let file = File::create(&path).map_err(|e| {
+                format!("Failed to create log file {}: {}", path.to_string_lossy(), e)
+            })?;
+
+            let file = LogFile(Arc::new(Mutex::new(file)));
+            Some(fmt::layer().json().with_writer(move || file.clone()))
+        }
+        None => None,
+    };
+
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(fmt::layer())
+        .with(json)
+        .try_init()
+        .map_err(|e| e.to_string())
+}
+
+/// The log file shared by all threads, every event is written under the lock in one piece.
+#[derive(Clone)]
+struct LogFile(Arc<Mutex<File>>);
+
+impl Write for LogFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut file = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        file.write_all(buf)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner()).flush()
+    }
+}
@@ -0,0 +1,123 @@
+use crate::{
+    collision::Aabb,
+    enemies::{MovementPattern, SpawnPoint},
+};
+use serde::{Deserialize, Serialize};
+use std::{fs, path::Path};
+
+/// Width and height of a single map tile.
+pub const TILE_SIZE: u16 = 50;
+
+const WALL: char = '#';
+const FLOOR: char = '.';
+const PLAYER_SPAWN: char = 'P';
+const PATROL_SPAWN: char = 'E';
+const CHASE_SPAWN: char = 'C';
+
+/// How far enemies spawned on a patrol tile walk to the right and back.
+const PATROL_DISTANCE: u16 = 4 * TILE_SIZE;
+
+/// Static level geometry and spawn points.
+///
+/// Maps are stored as text, one line per row of tiles, where `#` is a wall and `.` is floor.
+/// `P` marks a player spawn point, `E` a patrolling and `C` a chasing enemy spawn point,
+/// all three are floor as well.
+#[derive(Debug, Clone, Serialize, Deserialize, Hash, Eq, PartialEq)]
+pub struct TileMap {
+    walls: Vec<Aabb>,
+    player_spawns: Vec<(u16, u16)>,
+    enemy_spawns: Vec<SpawnPoint>,
+}
+
+impl TileMap {
+    pub fn new() -> TileMap {
+        TileMap {
+            walls: Vec::new(),
+            player_spawns: Vec::new(),
+            enemy_spawns: Vec::new(),
+        }
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<TileMap, String> {
+        let content = fs::read_to_string(path).map_err(|e| e.to_string())?;
+        TileMap::parse(&content)
+    }
+
+    pub fn parse(content: &str) -> Result<TileMap, String> {
+        let mut walls = Vec::new();
+        let mut player_spawns = Vec::new();
+        let mut enemy_spawns = Vec::new();
+
+        for (row, line) in content.lines().enumerate() {
+            let mut wall_start = None;
+
+            // Merge horizontal runs of wall tiles into a single box.
+            for (column, tile) in line.chars().chain(std::iter::once(FLOOR)).enumerate() {
+                let (x, y) = (column as u16 * TILE_SIZE, row as u16 * TILE_SIZE);
+
+                match tile {
+                    PLAYER_SPAWN => player_spawns.push((x, y)),
+                    PATROL_SPAWN => enemy_spawns.push(SpawnPoint::new(
+                        x,
+                        y,
+                        MovementPattern::Patrol {
+                            distance: PATROL_DISTANCE,
+                        },
+                    )),
+                    CHASE_SPAWN => {
+                        enemy_spawns.push(SpawnPoint::new(x, y, MovementPattern::ChaseNearestPlayer))
+                    }
+                    _ => {}
+                }
+
+                match tile {
+                    WALL => {
+                        if wall_start.is_none() {
+                            wall_start = Some(column);
+                        }
+                    }
+                    FLOOR | PLAYER_SPAWN | PATROL_SPAWN | CHASE_SPAWN => {
+                        if let Some(start) = wall_start.take() {
+                            walls.push(Aabb::new(
+                                start as u16 * TILE_SIZE,
+                                row as u16 * TILE_SIZE,
+                                (column - start) as u16 * TILE_SIZE,
+                                TILE_SIZE,
+                            ));
+                        }
+                    }
+                    other => {
+                        return Err(format!(
+                            "Unknown tile '{}' at row {}, column {}",
+                            other,
+                            row + 1,
+                            column + 1
+                        ))
+                    }
+                }
+            }
+        }
+
+        Ok(TileMap {
+            walls,
+            player_spawns,
+            enemy_spawns,
+        })
+    }
+
+    pub fn walls(&self) -> &[Aabb] {
+        &self.walls
+    }
+
+    pub fn player_spawns(&self) -> &[(u16, u16)] {
+        &self.player_spawns
+    }
+
+    pub fn enemy_spawns(&self) -> &[SpawnPoint] {
+        &self.enemy_spawns
+    }
+
+    pub fn is_blocked(&self, aabb: &Aabb) -> bool {
+        self.walls.iter().any(|wall| wall.intersects(aabb))
+    }
+}
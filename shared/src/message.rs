@@ -1,24 +1,64 @@
+use crate::{
+    checksum::WorldChecksum,
+    map::TileMap,
+    snapshot::WorldSnapshot,
+};
+pub use crate::command::ClientCommand;
 use net_sync::transport::{ClientId};
 use serde::{Serialize, Deserialize};
 use net_sync::synchronisation::{NetworkMessage, NetworkCommand};
 
-#[derive(Debug, Clone, Serialize, Deserialize, Hash, Eq, PartialEq)]
-pub enum ClientCommand {
-    MoveUp,
-    MoveRight,
-    MoveDown,
-    MoveLeft
-}
-
 #[derive(Clone, Serialize, Deserialize, Hash, Eq, PartialEq)]
 pub enum ClientMessage {
-    ConnectionRequest,
-    Disconnect
+    ConnectionRequest(String),
+    Disconnect,
+    Ping(u32),
+    Pong(u32),
+    Chat(String),
+    /// Entities that differed from the server's checksum at the command frame.
+    DesyncReport { command_frame: u32, uids: Vec<u32> },
+    /// The server corrected the prediction from this command frame on and the client resimulated.
+    Resimulated { command_frame: u32 },
 }
 
 #[derive(Clone, Serialize, Deserialize, Hash, Eq, PartialEq)]
 pub enum ServerMessage {
     ConnectionAccepted(ClientId),
+    Map(TileMap),
+    WorldSnapshot(WorldSnapshot),
+    /// Sent periodically so the client can check its world against the server's.
+    WorldChecksum(WorldChecksum),
+    Pong {
+        sequence: u32,
+        command_frame_offset: i32,
+        highest_seen: u32,
+        /// Ticks that took longer than the tick interval since the server started.
+        tick_overruns: u32,
+    },
+    Ping(u32),
+    CommandRejected {
+        command_frame: u32,
+        reason: CommandRejection,
+    },
+    CommandFrameFeedback {
+        buffered_frames: i32,
+    },
+    Chat {
+        from: String,
+        text: String,
+    },
+    Kicked {
+        reason: String,
+    },
+    ServerShuttingDown {
+        reason: String,
+    },
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Hash, Eq, PartialEq)]
+pub enum CommandRejection {
+    Late,
+    TooEarly,
 }
 
 impl NetworkMessage for ClientMessage {}
@@ -0,0 +1,76 @@
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use crate::{
+    collision::{Aabb, PICKUP_SIZE},
+    components::PickupKind,
+    map::TileMap,
+    SCREEN_HEIGHT, SCREEN_WIDTH,
+};
+
+/// How often a free position is searched for before giving up on spawning this frame.
+const SPAWN_ATTEMPTS: usize = 10;
+
+pub struct PickupSettings {
+    /// Spawn a pickup every this many command frames, 0 disables spawning.
+    pub spawn_interval: u32,
+    pub max_count: usize,
+    /// Chance that a spawned pickup is a gem instead of a coin.
+    pub gem_chance: f64,
+    seed: u64,
+    rng: StdRng,
+}
+
+impl PickupSettings {
+    pub fn new(spawn_interval: u32, max_count: usize) -> PickupSettings {
+        let seed = rand::random();
+
+        PickupSettings {
+            spawn_interval,
+            max_count,
+            gem_chance: 0.1,
+            seed,
+            rng: StdRng::seed_from_u64(seed),
+        }
+    }
+
+    /// Seeds the random spawns, so a replay spawns the same pickups as the recording.
+    pub fn with_seed(mut self, seed: u64) -> PickupSettings {
+        self.seed = seed;
+        self.rng = StdRng::seed_from_u64(seed);
+        self
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    pub fn random_kind(&mut self) -> PickupKind {
+        if self.rng.gen_bool(self.gem_chance) {
+            PickupKind::Gem
+        } else {
+            PickupKind::Coin
+        }
+    }
+
+    /// Finds a random position where a pickup does not overlap walls or any of the given boxes.
+    pub fn random_free_position(&mut self, map: &TileMap, occupied: &[Aabb]) -> Option<(u16, u16)> {
+        for _ in 0..SPAWN_ATTEMPTS {
+            let x = self.rng.gen_range(0, SCREEN_WIDTH as u16 - PICKUP_SIZE);
+            let y = self.rng.gen_range(0, SCREEN_HEIGHT as u16 - PICKUP_SIZE);
+            let pickup_box = Aabb::from_pickup_position(x, y);
+
+            if !map.is_blocked(&pickup_box)
+                && !occupied.iter().any(|aabb| aabb.intersects(&pickup_box))
+            {
+                return Some((x, y));
+            }
+        }
+
+        None
+    }
+}
+
+impl Default for PickupSettings {
+    fn default() -> Self {
+        PickupSettings::new(60, 10)
+    }
+}
@@ -0,0 +1,71 @@
+use net_sync::transport::ClientId;
+
+use crate::{map::TileMap, MAX_PLAYER_NAME_LENGTH};
+
+pub struct PlayerSettings {
+    pub spawn_points: Vec<(u16, u16)>,
+    pub max_health: u16,
+    /// Health lost per damage tick while touching an enemy.
+    pub enemy_damage: u16,
+    /// Apply damage every this many command frames, 0 disables damage.
+    pub damage_interval: u32,
+    next_spawn_point: usize,
+}
+
+impl PlayerSettings {
+    pub fn new(spawn_points: Vec<(u16, u16)>) -> PlayerSettings {
+        PlayerSettings {
+            spawn_points,
+            max_health: 100,
+            enemy_damage: 10,
+            damage_interval: 10,
+            next_spawn_point: 0,
+        }
+    }
+
+    /// Returns the spawn points round robin.
+    pub fn next_spawn_point(&mut self) -> (u16, u16) {
+        if self.spawn_points.is_empty() {
+            return (200, 200);
+        }
+
+        let spawn_point = self.spawn_points[self.next_spawn_point % self.spawn_points.len()];
+        self.next_spawn_point = (self.next_spawn_point + 1) % self.spawn_points.len();
+        spawn_point
+    }
+
+    /// Uses the player spawn points of the map, or the default ones if the map has none.
+    pub fn for_map(map: &TileMap) -> PlayerSettings {
+        if map.player_spawns().is_empty() {
+            PlayerSettings::default()
+        } else {
+            PlayerSettings::new(map.player_spawns().to_vec())
+        }
+    }
+}
+
+impl Default for PlayerSettings {
+    fn default() -> Self {
+        PlayerSettings::new(vec![(200, 200), (400, 200), (200, 350), (400, 350)])
+    }
+}
+
+/// Trims the name a client asked for and clamps it to `MAX_PLAYER_NAME_LENGTH` characters.
+///
+/// Names are drawn as text, which fails for empty strings,
+/// so a name without printable characters is replaced by `player-<client id>`.
+pub fn sanitize_player_name(name: &str, client_id: ClientId) -> String {
+    let name: String = name
+        .trim()
+        .chars()
+        .filter(|c| !c.is_control())
+        .take(MAX_PLAYER_NAME_LENGTH)
+        .collect();
+    let name = name.trim_end();
+
+    if name.is_empty() {
+        format!("player-{}", client_id)
+    } else {
+        name.to_string()
+    }
+}
@@ -0,0 +1,132 @@
+use std::{
+    fs::File,
+    io::{BufReader, BufWriter, ErrorKind, Write},
+    path::Path,
+};
+
+use net_sync::transport::ClientId;
+use serde::{Deserialize, Serialize};
+
+use crate::{map::TileMap, message::ClientCommand, snapshot::WorldSnapshot};
+
+/// Written once at the start of a replay file.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReplayHeader {
+    pub map: TileMap,
+    pub tick_rate: u32,
+    /// Seed of the random pickup spawns.
+    pub seed: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum ReplayEvent {
+    /// A client joined and its player was spawned.
+    Connected {
+        frame: u32,
+        client_id: ClientId,
+        uid: u32,
+        position: (u16, u16),
+    },
+    /// A client left or was kicked and its player was removed.
+    Disconnected { frame: u32, client_id: ClientId },
+    /// A command was applied by the server at `frame` as the command of `command_frame`,
+    /// it was sent by the client for `sent_frame`.
+    Command {
+        frame: u32,
+        command_frame: u32,
+        sent_frame: u32,
+        client_id: ClientId,
+        command: ClientCommand,
+    },
+    Snapshot(WorldSnapshot),
+    /// A client reported at `frame` that it resimulated from `command_frame` on.
+    Resimulated {
+        frame: u32,
+        client_id: ClientId,
+        command_frame: u32,
+    },
+}
+
+impl ReplayEvent {
+    /// The server command frame at which this event happened.
+    pub fn frame(&self) -> u32 {
+        match self {
+            ReplayEvent::Connected { frame, .. } => *frame,
+            ReplayEvent::Disconnected { frame, .. } => *frame,
+            ReplayEvent::Command { frame, .. } => *frame,
+            ReplayEvent::Snapshot(snapshot) => snapshot.command_frame,
+            ReplayEvent::Resimulated { frame, .. } => *frame,
+        }
+    }
+}
+
+/// Streams replay events to a file.
+pub struct ReplayWriter {
+    writer: BufWriter<File>,
+}
+
+impl ReplayWriter {
+    pub fn create<P: AsRef<Path>>(path: P, header: &ReplayHeader) -> Result<ReplayWriter, String> {
+        let file = File::create(path).map_err(|e| e.to_string())?;
+        let mut writer = BufWriter::new(file);
+
+        bincode::serialize_into(&mut writer, header).map_err(|e| e.to_string())?;
+
+        Ok(ReplayWriter { writer })
+    }
+
+    pub fn write(&mut self, event: &ReplayEvent) -> Result<(), String> {
+        bincode::serialize_into(&mut self.writer, event).map_err(|e| e.to_string())
+    }
+
+    pub fn flush(&mut self) -> Result<(), String> {
+        self.writer.flush().map_err(|e| e.to_string())
+    }
+}
+
+/// A replay file loaded into memory, events are ordered by frame.
+pub struct Replay {
+    pub header: ReplayHeader,
+    pub events: Vec<ReplayEvent>,
+}
+
+impl Replay {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Replay, String> {
+        let file = File::open(path).map_err(|e| e.to_string())?;
+        let mut reader = BufReader::new(file);
+
+        let header: ReplayHeader =
+            bincode::deserialize_from(&mut reader).map_err(|e| e.to_string())?;
+
+        let mut events = Vec::new();
+
+        // The file ends after the last event, or with a partial event if the recording was cut off,
+        // keep everything before it. Anything else means the file is corrupt.
+        loop {
+            match bincode::deserialize_from::<_, ReplayEvent>(&mut reader) {
+                Ok(event) => events.push(event),
+                Err(e) => match *e {
+                    bincode::ErrorKind::Io(ref io) if io.kind() == ErrorKind::UnexpectedEof => {
+                        break
+                    }
+                    _ => return Err(format!("Event {} is invalid: {}", events.len(), e)),
+                },
+            }
+        }
+
+        events.sort_by_key(|event| event.frame());
+
+        Ok(Replay { header, events })
+    }
+
+    /// Returns the events that happened at the given frame.
+    pub fn events_at(&self, frame: u32) -> &[ReplayEvent] {
+        let start = self.events.partition_point(|event| event.frame() < frame);
+        let end = self.events.partition_point(|event| event.frame() <= frame);
+        &self.events[start..end]
+    }
+
+    pub fn last_frame(&self) -> u32 {
+        self.events.last().map_or(0, |event| event.frame())
+    }
+}
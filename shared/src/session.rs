@@ -0,0 +1,117 @@
+//! The connection handshake, shared by the server, the client and the integration harness.
+//!
+//! A client sends `ConnectionRequest`, the server spawns its player and answers with
+//! `ConnectionAccepted` and the `Map`, and sends a `WorldSnapshot` of everything once the player
+//! exists. The client is synchronizing until the snapshot arrives and connected from then on.
+
+use legion::prelude::*;
+use legion_sync::components::UidComponent;
+use net_sync::{transport::ClientId, uid::UidAllocator};
+
+use crate::{
+    components::{Health, PlayerInfo, PlayerType, PlayerTypeOp, Position, Score},
+    map::TileMap,
+    message::ServerMessage,
+    players::PlayerSettings,
+};
+
+/// Clients that joined this tick and still need a world snapshot.
+pub struct SnapshotRequests {
+    clients: Vec<ClientId>,
+}
+
+impl SnapshotRequests {
+    pub fn new() -> SnapshotRequests {
+        SnapshotRequests {
+            clients: Vec::new(),
+        }
+    }
+
+    pub fn push(&mut self, client_id: ClientId) {
+        self.clients.push(client_id);
+    }
+
+    pub fn drain(&mut self) -> impl Iterator<Item = ClientId> + '_ {
+        self.clients.drain(..)
+    }
+}
+
+/// The player the server spawned for a client and the messages that accept its connection.
+pub struct AcceptedConnection {
+    pub uid: u32,
+    pub position: (u16, u16),
+    pub messages: Vec<ServerMessage>,
+}
+
+/// Spawns a player of the given client and returns its uid.
+pub fn spawn_player(
+    command: &mut CommandBuffer,
+    uid_allocator: &mut UidAllocator<Entity>,
+    client_id: ClientId,
+    position: (u16, u16),
+    max_health: u16,
+) -> u32 {
+    let entity = command
+        .start_entity()
+        .with_component(Position::new(position.0, position.1))
+        .with_component(PlayerType::new(PlayerTypeOp::Player))
+        .with_component(PlayerInfo::new(client_id))
+        .with_component(Health::new(max_health))
+        .with_component(Score::default())
+        .build();
+
+    let uid = uid_allocator.allocate(entity, None);
+    command.add_component(entity, UidComponent::new(uid));
+
+    uid
+}
+
+/// Accepts a client that asked to connect.
+///
+/// The players it already has are removed, a reconnecting client gets a fresh one at the next
+/// spawn point, and the client is queued for a world snapshot. The caller sends the returned
+/// messages to the client.
+pub fn accept_connection(
+    command: &mut CommandBuffer,
+    uid_allocator: &mut UidAllocator<Entity>,
+    player_settings: &mut PlayerSettings,
+    map: &TileMap,
+    snapshot_requests: &mut SnapshotRequests,
+    client_id: ClientId,
+    previous_players: Vec<Entity>,
+) -> AcceptedConnection {
+    for entity in previous_players {
+        command.delete(entity);
+        uid_allocator.deallocate(entity);
+    }
+
+    let position = player_settings.next_spawn_point();
+    let uid = spawn_player(
+        command,
+        uid_allocator,
+        client_id,
+        position,
+        player_settings.max_health,
+    );
+
+    snapshot_requests.push(client_id);
+
+    AcceptedConnection {
+        uid,
+        position,
+        messages: vec![
+            ServerMessage::ConnectionAccepted(client_id),
+            ServerMessage::Map(map.clone()),
+        ],
+    }
+}
+
+/// Whether the client handles the message as part of the handshake.
+pub fn is_handshake_message(message: &ServerMessage) -> bool {
+    matches!(
+        message,
+        ServerMessage::ConnectionAccepted(_)
+            | ServerMessage::Map(_)
+            | ServerMessage::WorldSnapshot(_)
+    )
+}
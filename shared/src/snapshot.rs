@@ -0,0 +1,93 @@
+use legion::prelude::{CommandBuffer, Entity};
+use legion_sync::components::UidComponent;
+use net_sync::transport::ClientId;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    checksum::checksum_of,
+    components::{Health, Pickup, PlayerInfo, PlayerType, Position, Score},
+};
+
+/// The synchronized components of a single entity.
+#[derive(Debug, Clone, Serialize, Deserialize, Hash, Eq, PartialEq)]
+pub struct EntitySnapshot {
+    pub uid: u32,
+    pub position: Option<(u16, u16)>,
+    pub player_type: Option<u16>,
+    pub client_id: Option<ClientId>,
+    pub health: Option<(u16, u16)>,
+    pub pickup: Option<(u16, u16)>,
+    pub score: Option<u32>,
+}
+
+impl EntitySnapshot {
+    pub fn new(
+        uid: u32,
+        position: Option<&Position>,
+        player_type: Option<&PlayerType>,
+        player_info: Option<&PlayerInfo>,
+        health: Option<&Health>,
+        pickup: Option<&Pickup>,
+        score: Option<&Score>,
+    ) -> EntitySnapshot {
+        EntitySnapshot {
+            uid,
+            position: position.map(|position| (position.x, position.y)),
+            player_type: player_type.map(|player_type| player_type.player_type),
+            client_id: player_info.map(|player_info| player_info.client_id()),
+            health: health.map(|health| (health.current, health.max)),
+            pickup: pickup.map(|pickup| (pickup.kind, pickup.value)),
+            score: score.map(|score| score.points),
+        }
+    }
+
+    /// Hash of all components, see `checksum_of`.
+    pub fn checksum(&self) -> u64 {
+        checksum_of(self)
+    }
+
+    /// Creates an entity with all components in this snapshot, the caller is responsible for allocating the uid.
+    pub fn spawn(&self, command: &mut CommandBuffer) -> Entity {
+        let entity = command
+            .start_entity()
+            .with_component(UidComponent::new(self.uid))
+            .build();
+
+        if let Some((x, y)) = self.position {
+            command.add_component(entity, Position::new(x, y));
+        }
+        if let Some(player_type) = self.player_type {
+            command.add_component(entity, PlayerType { player_type });
+        }
+        if let Some(client_id) = self.client_id {
+            command.add_component(entity, PlayerInfo::new(client_id));
+        }
+        if let Some((current, max)) = self.health {
+            command.add_component(entity, Health { current, max });
+        }
+        if let Some((kind, value)) = self.pickup {
+            command.add_component(entity, Pickup { kind, value });
+        }
+        if let Some(points) = self.score {
+            command.add_component(entity, Score { points });
+        }
+
+        entity
+    }
+}
+
+/// All synchronized entities at a command frame.
+#[derive(Debug, Clone, Serialize, Deserialize, Hash, Eq, PartialEq)]
+pub struct WorldSnapshot {
+    pub command_frame: u32,
+    pub entities: Vec<EntitySnapshot>,
+}
+
+impl WorldSnapshot {
+    pub fn new(command_frame: u32) -> WorldSnapshot {
+        WorldSnapshot {
+            command_frame,
+            entities: Vec::new(),
+        }
+    }
+}
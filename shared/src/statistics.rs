@@ -0,0 +1,194 @@
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+pub static STATISTICS_WINDOW: Duration = Duration::from_secs(1);
+
+pub struct NetworkStatistics {
+    rtt: Option<Duration>,
+    ping_sequence: u32,
+    pending_ping: Option<(u32, Instant)>,
+
+    last_received: Instant,
+
+    window_start: Instant,
+    window_bytes_in: u64,
+    window_bytes_out: u64,
+    window_packets_in: u64,
+    window_packets_out: u64,
+
+    bytes_in_per_second: u64,
+    bytes_out_per_second: u64,
+    packets_in_per_second: u64,
+    packets_out_per_second: u64,
+
+    total_bytes_in: u64,
+    total_bytes_out: u64,
+
+    command_frame_offset: i32,
+    unacknowledged_commands: VecDeque<u32>,
+    rejected_commands: u32,
+
+    resimulations: u32,
+    resimulated_commands: u32,
+}
+
+impl NetworkStatistics {
+    pub fn new() -> NetworkStatistics {
+        NetworkStatistics {
+            rtt: None,
+            ping_sequence: 0,
+            pending_ping: None,
+            last_received: Instant::now(),
+            window_start: Instant::now(),
+            window_bytes_in: 0,
+            window_bytes_out: 0,
+            window_packets_in: 0,
+            window_packets_out: 0,
+            bytes_in_per_second: 0,
+            bytes_out_per_second: 0,
+            packets_in_per_second: 0,
+            packets_out_per_second: 0,
+            total_bytes_in: 0,
+            total_bytes_out: 0,
+            command_frame_offset: 0,
+            unacknowledged_commands: VecDeque::new(),
+            rejected_commands: 0,
+            resimulations: 0,
+            resimulated_commands: 0,
+        }
+    }
+
+    /// Rolls the per second counters over once the statistics window has passed.
+    pub fn update(&mut self) {
+        let elapsed = self.window_start.elapsed();
+
+        if elapsed >= STATISTICS_WINDOW {
+            let per_second = |count: u64| (count as f64 / elapsed.as_secs_f64()) as u64;
+
+            self.bytes_in_per_second = per_second(self.window_bytes_in);
+            self.bytes_out_per_second = per_second(self.window_bytes_out);
+            self.packets_in_per_second = per_second(self.window_packets_in);
+            self.packets_out_per_second = per_second(self.window_packets_out);
+
+            self.window_bytes_in = 0;
+            self.window_bytes_out = 0;
+            self.window_packets_in = 0;
+            self.window_packets_out = 0;
+            self.window_start = Instant::now();
+        }
+    }
+
+    pub fn record_received(&mut self, bytes: u64) {
+        self.last_received = Instant::now();
+        self.window_bytes_in += bytes;
+        self.window_packets_in += 1;
+        self.total_bytes_in += bytes;
+    }
+
+    pub fn record_sent(&mut self, bytes: u64) {
+        self.window_bytes_out += bytes;
+        self.window_packets_out += 1;
+        self.total_bytes_out += bytes;
+    }
+
+    /// Starts a new ping and returns the sequence that should be sent to the other side.
+    pub fn start_ping(&mut self) -> u32 {
+        self.ping_sequence = self.ping_sequence.wrapping_add(1);
+        self.pending_ping = Some((self.ping_sequence, Instant::now()));
+        self.ping_sequence
+    }
+
+    /// Finishes the pending ping and returns the round trip time if the sequence matches.
+    pub fn finish_ping(&mut self, sequence: u32) -> Option<Duration> {
+        if let Some((pending_sequence, sent_at)) = self.pending_ping {
+            if pending_sequence == sequence {
+                self.rtt = Some(sent_at.elapsed());
+                self.pending_ping = None;
+                return self.rtt;
+            }
+        }
+
+        None
+    }
+
+    pub fn set_command_frame_offset(&mut self, offset: i32) {
+        self.command_frame_offset = offset;
+    }
+
+    pub fn record_command(&mut self, command_frame: u32) {
+        self.unacknowledged_commands.push_back(command_frame);
+    }
+
+    /// Acknowledges all commands up to and including the given command frame.
+    pub fn acknowledge_commands(&mut self, command_frame: u32) {
+        while let Some(frame) = self.unacknowledged_commands.front() {
+            if *frame > command_frame {
+                break;
+            }
+            self.unacknowledged_commands.pop_front();
+        }
+    }
+
+    pub fn record_rejected_command(&mut self) {
+        self.rejected_commands += 1;
+    }
+
+    pub fn record_resimulation(&mut self, commands: usize) {
+        self.resimulations += 1;
+        self.resimulated_commands += commands as u32;
+    }
+
+    pub fn since_last_received(&self) -> Duration {
+        self.last_received.elapsed()
+    }
+
+    pub fn rtt(&self) -> Option<Duration> {
+        self.rtt
+    }
+
+    pub fn bytes_in_per_second(&self) -> u64 {
+        self.bytes_in_per_second
+    }
+
+    pub fn bytes_out_per_second(&self) -> u64 {
+        self.bytes_out_per_second
+    }
+
+    pub fn packets_in_per_second(&self) -> u64 {
+        self.packets_in_per_second
+    }
+
+    pub fn packets_out_per_second(&self) -> u64 {
+        self.packets_out_per_second
+    }
+
+    pub fn total_bytes_in(&self) -> u64 {
+        self.total_bytes_in
+    }
+
+    pub fn total_bytes_out(&self) -> u64 {
+        self.total_bytes_out
+    }
+
+    pub fn command_frame_offset(&self) -> i32 {
+        self.command_frame_offset
+    }
+
+    pub fn unacknowledged_commands(&self) -> usize {
+        self.unacknowledged_commands.len()
+    }
+
+    pub fn rejected_commands(&self) -> u32 {
+        self.rejected_commands
+    }
+
+    pub fn resimulations(&self) -> u32 {
+        self.resimulations
+    }
+
+    pub fn resimulated_commands(&self) -> u32 {
+        self.resimulated_commands
+    }
+}
@@ -1,7 +1,35 @@
+use std::ops::DerefMut;
 use std::sync::{Mutex, Arc, MutexGuard, LockResult};
+use legion::prelude::*;
+use legion::systems::{resource::Resource, schedule::Builder};
+use legion_sync::components::UidComponent;
+use net_sync::synchronisation::{
+    ClientCommandBuffer, CommandFrameTicker, ModifiedComponentsBuffer, ResimulationBuffer,
+};
+use net_sync::tracker::Trackable;
+use net_sync::transport::{
+    ClientId, ClientToServerMessage, PostBox, PostOffice, ServerToClientMessage,
+};
+use net_sync::uid::UidAllocator;
 use sdl2::render::WindowCanvas;
 use sdl2::EventPump;
 use sdl2::ttf::Sdl2TtfContext;
+use tracing::debug;
+
+use crate::{
+    collision::Aabb,
+    command::{Command, CommandQueue, CommandTracker},
+    components::{Health, Pickup, PlayerInfo, PlayerType, PlayerTypeOp, Position, Score},
+    enemies::{Enemy, EnemySettings},
+    map::TileMap,
+    message::{ClientCommand, ClientMessage, ServerMessage},
+    pickups::PickupSettings,
+    players::PlayerSettings,
+    session::{is_handshake_message, SnapshotRequests},
+    snapshot::{EntitySnapshot, WorldSnapshot},
+    statistics::NetworkStatistics,
+    ConnectionInformation, ConnectionState,
+};
 
 pub struct WindowResource {
     window: Arc<Mutex<WindowCanvas>>,
@@ -32,4 +60,479 @@ impl WindowResource {
     pub fn tff(&self) -> LockResult<MutexGuard<Sdl2TtfContext>> {
         self.tff_context.lock()
     }
-}
\ No newline at end of file
+}
+
+/// Which side of the game a schedule simulates.
+pub enum SimulationRole {
+    /// The server owns the world and runs every gameplay rule.
+    Server,
+    /// A client only predicts the commands of its own player and resimulates them when the server
+    /// corrects it, the rest comes from the server.
+    Client,
+}
+
+/// Adds the gameplay simulation to a schedule.
+///
+/// The server and the clients both build their schedule with it, so the prediction runs
+/// the same systems as the authority and only the IO and rendering systems differ.
+/// `after_system` is called with the name of every added system, the server adds its
+/// profiling markers there.
+///
+/// Enemies move before the commands are applied, in the same order the server always ran them,
+/// so replays recorded before the simulation was shared still match.
+pub fn simulation_systems(
+    builder: Builder,
+    role: SimulationRole,
+    after_system: fn(Builder, &'static str) -> Builder,
+) -> Builder {
+    match role {
+        SimulationRole::Server => {
+            let builder = after_system(builder.add_system(enemy_system()), "enemy_system");
+            let builder = after_system(
+                builder.add_system(apply_commands_system::<ModifiedComponentsBuffer>()),
+                "apply_commands_system",
+            );
+            let builder = after_system(builder.add_system(collision_system()), "collision_system");
+            after_system(builder.add_system(pickup_system()), "pickup_system")
+        }
+        SimulationRole::Client => {
+            let builder = after_system(
+                builder.add_system(apply_commands_system::<ClientCommandBuffer<ClientCommand>>()),
+                "apply_commands_system",
+            );
+            after_system(builder.add_system(resimulation_system()), "resimulation_system")
+        }
+    }
+}
+
+/// Applies the queued commands to the players of the clients that sent them.
+pub fn apply_commands_system<T: CommandTracker + Resource>() -> Box<dyn Schedulable> {
+    SystemBuilder::new("apply_commands_system")
+        .write_resource::<CommandQueue>()
+        .write_resource::<T>()
+        .read_resource::<TileMap>()
+        .write_component::<Position>()
+        .read_component::<PlayerInfo>()
+        .read_component::<UidComponent>()
+        .with_query(<Read<PlayerInfo>>::query())
+        .build(|_, mut world, resource, query| {
+            let queue: &mut CommandQueue = &mut resource.0;
+            let tracker: &mut T = &mut resource.1;
+            let map: &TileMap = &resource.2;
+
+            for (client_id, command_frame, command) in queue.drain() {
+                let player = query
+                    .iter_entities(&mut world)
+                    .find(|(_, info)| info.client_id() == client_id)
+                    .map(|(entity, _)| entity);
+
+                if let Some(entity) = player {
+                    tracker.apply_tracked(command, &mut world, entity, map, command_frame);
+                }
+            }
+        })
+}
+
+/// Replays the commands the server corrected on top of the corrected state.
+///
+/// Other players stay where they are now while the commands are replayed, the client does not keep
+/// their positions per frame. A move that was blocked by another player on the server can therefore
+/// resimulate differently, the next correction from the server fixes that up.
+pub fn resimulation_system() -> Box<dyn Schedulable> {
+    SystemBuilder::new("resimulation_system")
+        .write_resource::<ResimulationBuffer<ClientCommand>>()
+        .write_resource::<NetworkStatistics>()
+        .read_resource::<ConnectionInformation>()
+        .read_resource::<TileMap>()
+        .write_component::<Position>()
+        .read_component::<PlayerInfo>()
+        .read_component::<UidComponent>()
+        .with_query(<Read<PlayerInfo>>::query())
+        .build(|_, mut world, resource, query| {
+            let resimulating: &mut ResimulationBuffer<ClientCommand> = &mut resource.0;
+            let statistics: &mut NetworkStatistics = &mut resource.1;
+            let connection_info: &ConnectionInformation = &resource.2;
+            let map: &TileMap = &resource.3;
+
+            for entry in resimulating.iter() {
+                statistics.record_resimulation(entry.to_resimmulate.len());
+
+                // The server disagreed with our prediction, replay our commands on top of the
+                // corrected state.
+                if connection_info.is_connected() {
+                    let player = query
+                        .iter_entities(&mut world)
+                        .find(|(_, info)| info.client_id() == connection_info.client_id())
+                        .map(|(entity, _)| entity);
+
+                    if let Some(entity) = player {
+                        for resimulate in entry.to_resimmulate.iter() {
+                            resimulate.command.apply(&mut world, entity, map);
+                        }
+                    }
+
+                    debug!(
+                        client_id = %connection_info.client_id(),
+                        command_frame = entry.start_command_frame,
+                        end_command_frame = entry.end_command_frame,
+                        commands = entry.to_resimmulate.len(),
+                        "Resimulated"
+                    );
+                }
+            }
+
+            resimulating.entries.clear();
+        })
+}
+
+pub fn enemy_system() -> Box<dyn Schedulable> {
+    SystemBuilder::new("enemy_system")
+        .write_resource::<EnemySettings>()
+        .write_resource::<UidAllocator<Entity>>()
+        .read_resource::<CommandFrameTicker>()
+        .write_resource::<ModifiedComponentsBuffer>()
+        .read_resource::<TileMap>()
+        .with_query(<(
+            Write<Position>,
+            Read<UidComponent>,
+            Write<Enemy>,
+        )>::query())
+        .with_query(<(Read<Position>, Read<PlayerInfo>)>::query())
+        .build(|command, mut world, resource, (enemy_query, player_query)| {
+            let settings: &mut EnemySettings = &mut resource.0;
+            let uid_allocator: &mut UidAllocator<Entity> = &mut resource.1;
+            let command_frame = resource.2.command_frame();
+            let modified_components_buffer: &mut ModifiedComponentsBuffer = &mut resource.3;
+            let map: &TileMap = &resource.4;
+
+            let players: Vec<(u16, u16)> = player_query
+                .iter(&mut world)
+                .map(|(pos, _)| (pos.x, pos.y))
+                .collect();
+
+            let mut enemy_count = 0;
+
+            for (entity, (mut pos, uid, mut enemy)) in enemy_query.iter_entities_mut(&mut world) {
+                if let Some(lifetime) = settings.lifetime {
+                    if command_frame.saturating_sub(enemy.spawned_at) >= lifetime {
+                        debug!(uid = uid.uid(), command_frame, "Despawning enemy");
+                        command.delete(entity);
+                        uid_allocator.deallocate(entity);
+                        continue;
+                    }
+                }
+
+                enemy_count += 1;
+
+                if command_frame.checked_rem(settings.move_interval) == Some(0) {
+                    let new_pos =
+                        enemy.next_position((pos.x, pos.y), &players, map, settings.velocity);
+
+                    if new_pos != (pos.x, pos.y) {
+                        let mut pos =
+                            pos.server_track(modified_components_buffer, **uid, command_frame);
+                        pos.deref_mut().set(new_pos);
+                    }
+                }
+            }
+
+            if command_frame.checked_rem(settings.spawn_interval) == Some(0)
+                && enemy_count < settings.max_count
+            {
+                if let Some(spawn_point) = settings.next_spawn_point() {
+                    let entity = command
+                        .start_entity()
+                        .with_component(Position::new(spawn_point.x, spawn_point.y))
+                        .with_component(PlayerType::new(PlayerTypeOp::Enemy))
+                        .with_component(Enemy::new(&spawn_point, command_frame))
+                        .build();
+
+                    let component = UidComponent::new(uid_allocator.allocate(entity, None));
+                    command.add_component(entity, component);
+                }
+            }
+        })
+}
+
+pub fn collision_system() -> Box<dyn Schedulable> {
+    SystemBuilder::new("collision_system")
+        .write_resource::<PlayerSettings>()
+        .read_resource::<CommandFrameTicker>()
+        .write_resource::<ModifiedComponentsBuffer>()
+        .with_query(<(
+            Write<Position>,
+            Write<Health>,
+            Read<UidComponent>,
+            Read<PlayerInfo>,
+        )>::query())
+        .with_query(<(Read<Position>, Read<Enemy>)>::query())
+        .build(|_, mut world, resource, (player_query, enemy_query)| {
+            let settings: &mut PlayerSettings = &mut resource.0;
+            let command_frame = resource.1.command_frame();
+            let modified_components_buffer: &mut ModifiedComponentsBuffer = &mut resource.2;
+
+            if command_frame.checked_rem(settings.damage_interval) != Some(0) {
+                return;
+            }
+
+            let enemies: Vec<Aabb> = enemy_query
+                .iter(&mut world)
+                .map(|(pos, _)| Aabb::from(&*pos))
+                .collect();
+
+            for (mut pos, mut health, uid, player_info) in player_query.iter_mut(&mut world) {
+                let player_box = Aabb::from(&*pos);
+
+                if !enemies.iter().any(|enemy| enemy.intersects(&player_box)) {
+                    continue;
+                }
+
+                let mut health =
+                    health.server_track(modified_components_buffer, **uid, command_frame);
+                health.damage(settings.enemy_damage);
+
+                if health.is_dead() {
+                    debug!(
+                        client_id = %player_info.client_id(),
+                        uid = uid.uid(),
+                        command_frame,
+                        "Player died, respawning"
+                    );
+
+                    health.restore();
+
+                    let mut pos = pos.server_track(modified_components_buffer, **uid, command_frame);
+                    pos.deref_mut().set(settings.next_spawn_point());
+                }
+            }
+        })
+}
+
+pub fn pickup_system() -> Box<dyn Schedulable> {
+    SystemBuilder::new("pickup_system")
+        .write_resource::<PickupSettings>()
+        .write_resource::<UidAllocator<Entity>>()
+        .read_resource::<CommandFrameTicker>()
+        .write_resource::<ModifiedComponentsBuffer>()
+        .read_resource::<TileMap>()
+        .with_query(<(Read<Position>, Read<Pickup>, Read<UidComponent>)>::query())
+        .with_query(<(
+            Read<Position>,
+            Write<Score>,
+            Read<UidComponent>,
+            Read<PlayerInfo>,
+        )>::query())
+        .build(|command, mut world, resource, (pickup_query, player_query)| {
+            let settings: &mut PickupSettings = &mut resource.0;
+            let uid_allocator: &mut UidAllocator<Entity> = &mut resource.1;
+            let command_frame = resource.2.command_frame();
+            let modified_components_buffer: &mut ModifiedComponentsBuffer = &mut resource.3;
+            let map: &TileMap = &resource.4;
+
+            let mut pickups: Vec<(Entity, Aabb, u16)> = pickup_query
+                .iter_entities(&mut world)
+                .map(|(entity, (pos, pickup, _))| {
+                    (entity, Aabb::from_pickup_position(pos.x, pos.y), pickup.value)
+                })
+                .collect();
+
+            let mut occupied = Vec::new();
+
+            for (pos, mut score, uid, player_info) in player_query.iter_mut(&mut world) {
+                let player_box = Aabb::from(&*pos);
+                occupied.push(player_box);
+
+                let (collected, remaining): (Vec<_>, Vec<_>) = pickups
+                    .into_iter()
+                    .partition(|(_, pickup_box, _)| pickup_box.intersects(&player_box));
+                pickups = remaining;
+
+                if collected.is_empty() {
+                    continue;
+                }
+
+                let mut score = score.server_track(modified_components_buffer, **uid, command_frame);
+
+                for (entity, _, value) in collected {
+                    debug!(
+                        client_id = %player_info.client_id(),
+                        uid = uid.uid(),
+                        command_frame,
+                        value,
+                        "Player collected pickup"
+                    );
+
+                    score.add(value);
+                    command.delete(entity);
+                    uid_allocator.deallocate(entity);
+                }
+            }
+
+            if command_frame.checked_rem(settings.spawn_interval) == Some(0)
+                && pickups.len() < settings.max_count
+            {
+                occupied.extend(pickups.iter().map(|(_, pickup_box, _)| *pickup_box));
+
+                if let Some((x, y)) = settings.random_free_position(map, &occupied) {
+                    let entity = command
+                        .start_entity()
+                        .with_component(Position::new(x, y))
+                        .with_component(Pickup::new(settings.random_kind()))
+                        .build();
+
+                    let component = UidComponent::new(uid_allocator.allocate(entity, None));
+                    command.add_component(entity, component);
+                }
+            }
+        })
+}
+
+/// Sends the clients that joined this tick everything that already exists.
+///
+/// Runs after the command buffers are flushed, so entities spawned this tick, including the new
+/// player itself, are part of the snapshot.
+pub fn world_snapshot_system() -> Box<dyn Schedulable> {
+    SystemBuilder::new("world_snapshot_system")
+        .write_resource::<PostOffice<ServerMessage, ClientMessage, ClientCommand>>()
+        .read_resource::<CommandFrameTicker>()
+        .write_resource::<SnapshotRequests>()
+        .with_query(<(
+            Read<UidComponent>,
+            TryRead<Position>,
+            TryRead<PlayerType>,
+            TryRead<PlayerInfo>,
+            TryRead<Health>,
+            TryRead<Pickup>,
+            TryRead<Score>,
+        )>::query())
+        .build(|_, mut world, resource, query| {
+            let postoffice: &mut PostOffice<ServerMessage, ClientMessage, ClientCommand> =
+                &mut resource.0;
+            let command_frame = resource.1.command_frame();
+            let requests: Vec<ClientId> = resource.2.drain().collect();
+
+            if requests.is_empty() {
+                return;
+            }
+
+            let entities: Vec<EntitySnapshot> = query
+                .iter(&mut world)
+                .map(|(uid, pos, player_type, info, health, pickup, score)| {
+                    EntitySnapshot::new(
+                        uid.uid(),
+                        pos.as_deref(),
+                        player_type.as_deref(),
+                        info.as_deref(),
+                        health.as_deref(),
+                        pickup.as_deref(),
+                        score.as_deref(),
+                    )
+                })
+                .collect();
+
+            for (client_id, client) in postoffice.clients_mut() {
+                if !requests.contains(client_id) {
+                    continue;
+                }
+
+                // Late joining clients only receive changes through synchronisation,
+                // so they get everything that already exists up front.
+                let mut snapshot = WorldSnapshot::new(command_frame);
+                snapshot.entities = entities.clone();
+
+                debug!(
+                    client_id = %client_id,
+                    command_frame,
+                    entities = snapshot.entities.len(),
+                    "Sending world snapshot"
+                );
+
+                client
+                    .postbox_mut()
+                    .send(ServerToClientMessage::Message(ServerMessage::WorldSnapshot(
+                        snapshot,
+                    )));
+            }
+        })
+}
+
+/// Follows the connection handshake on a client, see `session`.
+///
+/// Runs before the other message systems and takes the handshake messages out of the inbox.
+/// Entities that were already replicated are replaced by the full state of the world snapshot.
+pub fn client_handshake_system() -> Box<dyn Schedulable> {
+    SystemBuilder::new("client_handshake_system")
+        .write_resource::<PostBox<
+            ServerToClientMessage<ServerMessage>,
+            ClientToServerMessage<ClientMessage, ClientCommand>,
+        >>()
+        .write_resource::<ConnectionInformation>()
+        .write_resource::<TileMap>()
+        .write_resource::<NetworkStatistics>()
+        .write_resource::<UidAllocator<Entity>>()
+        .with_query(<Read<UidComponent>>::query())
+        .build(|command, mut world, resource, query| {
+            let postbox: &mut PostBox<
+                ServerToClientMessage<ServerMessage>,
+                ClientToServerMessage<ClientMessage, ClientCommand>,
+            > = &mut resource.0;
+            let connection_info: &mut ConnectionInformation = &mut resource.1;
+            let map: &mut TileMap = &mut resource.2;
+            let statistics: &mut NetworkStatistics = &mut resource.3;
+            let uid_allocator: &mut UidAllocator<Entity> = &mut resource.4;
+
+            let inbox = postbox.drain_inbox(|message| match message {
+                ServerToClientMessage::Message(message) => is_handshake_message(message),
+                _ => false,
+            });
+
+            let mut spawned: Vec<EntitySnapshot> = Vec::new();
+
+            for message in inbox {
+                statistics.record_received(bincode::serialized_size(&message).unwrap_or(0));
+
+                if let ServerToClientMessage::Message(message) = message {
+                    match message {
+                        ServerMessage::ConnectionAccepted(client_id) => {
+                            connection_info.set_synchronizing(client_id);
+                        }
+                        ServerMessage::Map(new_map) => {
+                            *map = new_map;
+                        }
+                        ServerMessage::WorldSnapshot(snapshot) => {
+                            if *connection_info.connection_state()
+                                != ConnectionState::Synchronizing
+                            {
+                                continue;
+                            }
+
+                            debug!(
+                                client_id = %connection_info.client_id(),
+                                command_frame = snapshot.command_frame,
+                                entities = snapshot.entities.len(),
+                                "Received world snapshot"
+                            );
+
+                            connection_info.set_connected(snapshot.command_frame);
+                            spawned.extend(snapshot.entities);
+                        }
+                        _ => {}
+                    }
+                }
+            }
+
+            for (entity, uid) in query.iter_entities(&mut world) {
+                let uid = uid.uid();
+
+                if spawned.iter().any(|spawn| spawn.uid == uid) {
+                    command.delete(entity);
+                    uid_allocator.deallocate(entity);
+                }
+            }
+
+            for entity_snapshot in spawned.iter() {
+                let entity = entity_snapshot.spawn(command);
+                uid_allocator.allocate(entity, Some(entity_snapshot.uid));
+            }
+        })
+}